@@ -0,0 +1,6713 @@
+//! 简单的本地文件同步。
+//!
+//! 命令行程序的逻辑都在这里，`main.rs` 只调用 [`run`]。也可以在自己的程序中使用分析、执行部分：
+//! 用 [`read_config`] 读取配置，[`get_dict_info`] 扫描两侧目录，[`DecisionTask`] 得出 [`DecisionResult`]，
+//! 再交给 [`DecisionExecuteTask`] 执行。
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! let contexts = ssync::read_config(&["ssync.yml".to_string()])?;
+//! let context = Arc::new(contexts[0].clone());
+//! let (src_dict_info, to_dict_info) = ssync::get_dict_info(&context, 0)?;
+//! let decision = ssync::DecisionTask::new(Arc::new(src_dict_info), Arc::new(to_dict_info), context.clone())
+//!     .make_decision()?;
+//! for item in decision.add_items.values().flatten() {
+//!     println!("{:?} {}", item.action, item.dest_file_info.relative_path());
+//! }
+//! ssync::DecisionExecuteTask::new(decision, context).execute()?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+// 枚举成员沿用全大写的命名
+#![allow(clippy::upper_case_acronyms)]
+
+use std::{fs, io, thread};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::{Arc, mpsc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use config::{Config, Value, ValueKind};
+use filetime::FileTime;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use indicatif::{ProgressBar, ProgressStyle};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// 命令行入口：解析参数，同步各组目录，返回进程的退出码
+pub fn run() -> Result<ExitCode> {
+    run_with_args(Args::parse())
+}
+
+/// 按已解析的命令行参数运行
+fn run_with_args(mut args: Args) -> Result<ExitCode> {
+    // 监听模式无人值守，所有确认都视为同意
+    args.yes |= args.watch;
+    if args.config_check {
+        return Ok(ExitCode::from(if check_config(&args.file) { 0 } else { 1 }));
+    }
+    let contexts = read_config(&args.file)?;
+    if contexts.len() > 1 && (args.save_plan.is_some() || args.apply_plan.is_some() || args.output == OutputFormat::Json) {
+        bail!("配置了多组同步目录时不支持 --save-plan、--apply-plan、--output json");
+    }
+    if args.watch && args.interactive {
+        bail!("--watch 无人值守，不能与 --interactive 同时使用");
+    }
+    if args.watch && (args.save_plan.is_some() || args.apply_plan.is_some() || args.output == OutputFormat::Json) {
+        bail!("--watch 不能与 --save-plan、--apply-plan、--output json 同时使用");
+    }
+    let report = match &args.report_csv {
+        Some(path) => Some(Arc::new(CsvReport::create(path)?)),
+        None => None,
+    };
+
+    let has_changes = match sync_all(&args, &contexts, &report) {
+        Ok(has_changes) => has_changes,
+        Err(e) if e.is::<Declined>() => {
+            return Ok(ExitCode::from(if args.detailed_exit_code { DECLINED_EXIT_CODE } else { 0 }));
+        }
+        Err(e) if args.detailed_exit_code && e.is::<OperationsFailed>() => {
+            eprintln!("Error: {:?}", e);
+            return Ok(ExitCode::from(FAILED_EXIT_CODE));
+        }
+        Err(e) => return Err(e),
+    };
+
+    if args.watch {
+        watch(&args, contexts, report)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let code = if !has_changes {
+        if args.exit_nonzero_on_noop { NOOP_EXIT_CODE } else { 0 }
+    } else if args.detailed_exit_code {
+        CHANGED_EXIT_CODE
+    } else {
+        0
+    };
+    if has_changes && args.save_plan.is_none() && !args.stats_only && args.output == OutputFormat::Text {
+        ready_to_exit(args.yes);
+    }
+    Ok(ExitCode::from(code))
+}
+
+/// 依次同步每组目录，返回是否有需要同步的内容。
+/// --keep-going 时某组有失败的操作也继续同步其余各组，最后汇总失败的数量
+fn sync_all(args: &Args, contexts: &[SyncContext], report: &Option<Arc<CsvReport>>) -> Result<bool> {
+    let since = args.since.as_deref().map(parse_since).transpose()?;
+    let pair_count = contexts.len();
+    let mut has_changes = false;
+    let mut failed_count = 0;
+    for (i, context) in contexts.iter().enumerate() {
+        if pair_count > 1 {
+            println!("——第 {}/{} 组：{} -> {}——", i + 1, pair_count, context.from.path, context.to.path);
+        }
+        match sync_pair(args, context_for_run(args, context, since), report.clone()) {
+            Ok(changed) => has_changes |= changed,
+            Err(e) => match e.downcast_ref::<OperationsFailed>() {
+                Some(OperationsFailed(count)) => {
+                    has_changes = true;
+                    failed_count += count;
+                }
+                None => return Err(e),
+            },
+        }
+    }
+    if failed_count > 0 {
+        return Err(OperationsFailed(failed_count).into());
+    }
+    Ok(has_changes)
+}
+
+/// 一组目录本次同步用的配置：应用命令行参数中针对本次运行的选项，并从现在开始计算运行期间
+fn context_for_run(args: &Args, context: &SyncContext, since: Option<SystemTime>) -> SyncContext {
+    let mut context = context.clone();
+    context.force_copy = args.force_copy.iter()
+        .map(|path| PathBuf::from(path.trim_start_matches("./")))
+        .collect();
+    context.prime = args.prime;
+    context.include_empty_dirs = args.include_empty_dirs;
+    context.max_depth = args.max_depth;
+    context.since = since;
+    context.run_started = SystemTime::now();
+    context
+}
+
+/// 两次同步之间等待变化平息的时间，期间的事件合并为一次同步
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// --watch：首次同步后监听各组的源目录，有变化（include、exclude 等过滤后仍相关）时重新同步该组，
+/// 直到进程被结束
+fn watch(args: &Args, contexts: Vec<SyncContext>, report: Option<Arc<CsvReport>>) -> Result<()> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut roots = Vec::new();
+    for context in &contexts {
+        let root = fs::canonicalize(&context.from.path)?;
+        let mode = if context.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        watcher.watch(&root, mode)?;
+        roots.push(root);
+    }
+    println!("正在监听源目录的变化，按 Ctrl+C 退出");
+
+    // 变化的路径属于哪一组；路径本身或其上层目录被过滤掉时不相关
+    let relevant_pair = |path: &Path| roots.iter().zip(&contexts).position(|(root, context)| {
+        path.starts_with(root) && path.ancestors().take_while(|dir| dir != root).all(|dir| {
+            DirectoryInfo::_check_include_and_exclude(
+                dir.to_str().unwrap(), root.to_str().unwrap(), context, &OperateDirection::FROM,
+            )
+        })
+    });
+
+    loop {
+        let mut changed = HashSet::new();
+        // 等到第一个事件后，继续收集直到 WATCH_DEBOUNCE 内没有新事件
+        let mut event = rx.recv()?;
+        loop {
+            match event {
+                // 同步时读取源文件也会产生访问事件，忽略
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    changed.extend(event.paths.iter().filter_map(|path| relevant_pair(path)));
+                }
+                Ok(_) => {}
+                Err(e) => println!("警告: 监听出错: {}", e),
+            }
+            event = match rx.recv_timeout(WATCH_DEBOUNCE) {
+                Ok(event) => event,
+                Err(_) => break,
+            };
+        }
+
+        let mut changed: Vec<usize> = changed.into_iter().collect();
+        changed.sort();
+        for i in changed {
+            let context = &contexts[i];
+            println!("——检测到变化，重新同步：{} -> {}——", context.from.path, context.to.path);
+            // --since 不能与 --watch 同时使用。出错时只报告，继续监听
+            if let Err(e) = sync_pair(args, context_for_run(args, context, None), report.clone()) {
+                println!("同步失败: {:#}", e);
+            }
+        }
+    }
+}
+
+/// 同步一组目录，返回是否有需要执行的操作
+fn sync_pair(args: &Args, mut context: SyncContext, report: Option<Arc<CsvReport>>) -> Result<bool> {
+    // 输出 JSON 时标准输出只留给计划
+    if args.output == OutputFormat::Text && !args.quiet {
+        println!("加载配置: {:#?}", context);
+    }
+    check_paths_exist(&context)?;
+    check_paths_not_overlapping(&context)?;
+    let zip_destination = ZipDestination::is_zip_path(&context.to.path);
+    // 本次扫描前的 USN 日志位置，同步完成后保存，下次从这里开始读取
+    let mut usn_state = None;
+    if context.mode == SyncMode::BIDIRECTIONAL {
+        if zip_destination {
+            bail!("目标为压缩包时不支持 mode: bidirectional");
+        }
+        if args.save_plan.is_some() || args.apply_plan.is_some() || context.since.is_some() {
+            bail!("mode: bidirectional 不支持 --save-plan、--apply-plan、--since");
+        }
+        context.last_synced = BidirectionalState::load(&context.to.path);
+    }
+    if context.state_db {
+        if zip_destination {
+            bail!("目标为压缩包时不支持 state_db");
+        }
+        context.synced_files = load_state_db(&context.to.path)?.map(Arc::new);
+    }
+    let decision_result = if let Some(plan_path) = &args.apply_plan {
+        if zip_destination {
+            bail!("目标为压缩包时不支持 --apply-plan");
+        }
+        let decision_result = DecisionResult::from_saved_plan(&fs::read_to_string(plan_path)?, &context)?;
+        println!("已加载计划: {}", plan_path);
+        decision_result
+    } else if zip_destination {
+        ZipDestination::make_decision(&context, args.scan_concurrency)?
+    } else {
+        if (args.dry_run || args.stats_only) && !Path::new(&context.to.path).exists() {
+            bail!("目标目录不存在，演练模式不会创建: {}", context.to.path);
+        }
+        ensure_dest_exists(&context, args.create_dest, args.yes)?;
+        if let (Some(retention_days), Some(trash_root)) = (context.trash_retention_days, context.trash_root()) {
+            if !args.dry_run && !args.stats_only {
+                purge_trash(&trash_root, retention_days)?;
+            }
+        }
+        if context.usn_journal {
+            let (changed_dirs, journal_state) = usn_changed_dirs(&context);
+            context.changed_dirs = changed_dirs.map(Arc::new);
+            usn_state = journal_state;
+        }
+        let (src_dict_info, to_dict_info) = get_dict_info(&context, args.scan_concurrency)?;
+        if args.output == OutputFormat::Text && !args.quiet {
+            println!("已加载目录信息");
+            print_filtered(&src_dict_info, &to_dict_info, args.verbose);
+        }
+
+        if args.pipeline {
+            let context = Arc::new(context);
+            if run_pipeline(args, context.clone(), src_dict_info, to_dict_info, report)? && !args.dry_run {
+                save_sync_state(&context, usn_state, &HashSet::new())?;
+            }
+            return Ok(true);
+        }
+        DecisionTask::new(
+            Arc::new(src_dict_info),
+            Arc::new(to_dict_info),
+            Arc::new(context.clone()),
+        ).make_decision()?
+    };
+    let context = Arc::new(context);
+
+    let summary_options = SummaryOptions {
+        max_lines: if args.quiet { Some(0) } else { Some(args.max_summary_lines).filter(|&lines| lines > 0) },
+        absolute_paths: args.absolute_paths,
+        deletions_disabled: !context.delete || context.since.is_some(),
+    };
+    if args.output == OutputFormat::Json {
+        println!("{}", decision_result.to_json()?);
+        return Ok(!decision_result.is_empty());
+    }
+    println!("{}", decision_result.summary_with_options(&summary_options));
+    if args.stats_only {
+        println!("{}", decision_result.counts_summary());
+        return Ok(!decision_result.is_empty());
+    }
+
+    if context.long_path_error && decision_result.has_skipped() {
+        bail!("有目标路径超过 max_path_length（{}），已取消执行", context.max_path_length.unwrap());
+    }
+
+    if let Some(plan_path) = &args.save_plan {
+        fs::write(plan_path, decision_result.to_saved_plan(&context)?)?;
+        println!("计划已保存到 {}，可用 --apply-plan 执行", plan_path);
+        return Ok(!decision_result.is_empty());
+    }
+
+    if decision_result.is_empty() {
+        if !args.dry_run {
+            save_sync_state(&context, usn_state, &decision_result.conflict_paths())?;
+        }
+        if decision_result.has_conflicts() {
+            println!("没有可执行的操作，冲突需手动处理");
+        } else {
+            println!("风平浪静，下次再见");
+        }
+        return Ok(false);
+    }
+
+    if let Some(command) = &context.approve_command {
+        if !approve_by_command(command, &decision_result)? {
+            bail!("审批命令未通过，已取消执行: {}", command);
+        }
+        println!("审批命令已通过");
+    }
+
+    let changed_count = decision_result.total_count();
+    if !args.dry_run {
+        check_continue("继续执行文件操作？", args.yes)?;
+        if let Some(command) = &context.pre_hook {
+            run_hook("pre_hook", command, &context, changed_count)?;
+        }
+    }
+
+    if zip_destination {
+        if args.dry_run {
+            println!("DRY RUN — 未改动任何文件");
+            return Ok(true);
+        }
+        ZipDestination::execute(&decision_result, &context)?;
+        if let Some(command) = &context.post_hook {
+            run_hook("post_hook", command, &context, changed_count)?;
+        }
+        return Ok(true);
+    }
+
+    let conflicts = decision_result.conflict_paths();
+    let mut execute_task = DecisionExecuteTask::new(decision_result, context.clone())
+        .with_dry_run(args.dry_run)
+        .with_keep_going(args.keep_going)
+        .with_quiet(args.quiet);
+    if args.interactive {
+        execute_task = execute_task.with_interactive(args.interactive_updates);
+    } else if !args.verbose && !args.quiet {
+        execute_task = execute_task.with_progress_bar();
+    }
+    if let Some(report) = report {
+        execute_task = execute_task.with_csv_report(report);
+    }
+    if let Some(log_file) = &context.log_file {
+        execute_task = execute_task.with_audit_log(AuditLog::open(log_file)?);
+    }
+    if execute_task.execute()? && !args.dry_run {
+        save_sync_state(&context, usn_state, &conflicts)?;
+        if let Some(command) = &context.post_hook {
+            run_hook("post_hook", command, &context, changed_count)?;
+        }
+    }
+    Ok(true)
+}
+
+/// 显示被过滤规则排除的条目数，--verbose 时逐项列出命中的规则
+fn print_filtered(src_dict_info: &DirectoryInfo, to_dict_info: &DirectoryInfo, verbose: bool) {
+    let (src_filtered, to_filtered) = (src_dict_info.all_filtered(), to_dict_info.all_filtered());
+    if src_filtered.is_empty() && to_filtered.is_empty() {
+        return;
+    }
+    println!("过滤规则排除了源目录 {} 项、目标目录 {} 项（排除的目录不再进入）",
+             src_filtered.len(), to_filtered.len());
+    if verbose {
+        for (side, filtered) in [("源", src_filtered), ("目标", to_filtered)] {
+            for (path, rule) in filtered {
+                println!("\t[{}] {} <- {}", side, path, rule);
+            }
+        }
+    }
+}
+
+/// 流水线模式：每分析完一层目录就把这一层的操作交给执行，分析与复制同时进行。
+/// 不会预先生成完整计划，因此只在开始前确认一次，也不支持审批命令。返回是否全部执行完毕
+fn run_pipeline(args: &Args, context: Arc<SyncContext>,
+                src_dict_info: DirectoryInfo, to_dict_info: DirectoryInfo,
+                report: Option<Arc<CsvReport>>) -> Result<bool> {
+    if context.approve_command.is_some() {
+        bail!("流水线模式不会预先生成完整计划，不能与 approve_command 同时使用");
+    }
+    if context.pre_hook.is_some() || context.post_hook.is_some() {
+        bail!("流水线模式无法预先得知操作数，不能与 pre_hook、post_hook 同时使用");
+    }
+    if context.detect_renames {
+        bail!("流水线模式逐层执行，无法跨目录配对新增和删除，不能与 detect_renames 同时使用");
+    }
+    if context.mode == SyncMode::BIDIRECTIONAL {
+        bail!("流水线模式无法在结束前汇总冲突，不能与 mode: bidirectional 同时使用");
+    }
+    check_continue("流水线模式会边分析边执行，不预先展示分析结果，继续？", args.yes)?;
+
+    // 开始分析前打开，出错时不会留下运行中的分析线程
+    let audit_log = context.log_file.as_deref().map(AuditLog::open).transpose()?;
+    let (tx, rx) = mpsc::channel();
+    let decision_task = DecisionTask::new(
+        Arc::new(src_dict_info),
+        Arc::new(to_dict_info),
+        context.clone(),
+    ).with_pipeline(tx);
+    let decision_handle = thread::spawn(move || decision_task.make_decision());
+
+    let mut execute_task = DecisionExecuteTask::new(DecisionResult::for_context(&context), context)
+        .with_dry_run(args.dry_run)
+        .with_keep_going(args.keep_going)
+        .with_quiet(args.quiet);
+    if args.interactive {
+        execute_task = execute_task.with_interactive(args.interactive_updates);
+    } else if !args.verbose && !args.quiet {
+        execute_task = execute_task.with_progress_bar();
+    }
+    if let Some(report) = report {
+        execute_task = execute_task.with_csv_report(report);
+    }
+    if let Some(audit_log) = audit_log {
+        execute_task = execute_task.with_audit_log(audit_log);
+    }
+    // 执行出错时先等分析线程结束（它的发送会被忽略），再返回错误
+    let completed = execute_task.execute_pipeline(rx);
+    decision_handle.join().unwrap()?;
+    completed
+}
+
+#[derive(Parser, Debug)]
+#[clap(version, about = "简单的本地文件同步", long_about = None)]
+struct Args {
+    /// 配置文件路径，可多次指定（如 `-f base.yml -f local.yml`），按顺序合并，后面的覆盖前面的。
+    /// 表按键合并，数组（如 exclude）整体替换而不是追加
+    #[clap(default_value = "ssync.yml", short, long, value_parser)]
+    file: Vec<String>,
+    /// 无视比较结果强制重新复制的文件（相对路径），可多次指定
+    #[clap(long = "force-copy", value_parser)]
+    force_copy: Vec<String>,
+    /// 没有需要同步的内容时以退出码 2 结束，便于调度系统区分“检查过但无事可做”
+    #[clap(long, value_parser)]
+    exit_nonzero_on_noop: bool,
+    /// 用退出码区分结果：0 无需同步，3 有需要同步的内容（--dry-run、--save-plan 时为预览），
+    /// 4 --keep-going 收集到失败的操作，5 在确认提示处取消；其他错误仍为 1
+    #[clap(long, value_parser)]
+    detailed_exit_code: bool,
+    /// 分析结果中每类操作最多列出的条目数，0 表示不限制
+    #[clap(long, value_parser, default_value_t = 300)]
+    max_summary_lines: usize,
+    /// 目标目录不存在时直接创建，不再询问。--yes 不会自动创建目标目录，需要时同时指定本选项
+    #[clap(long, value_parser)]
+    create_dest: bool,
+    /// 扫描时最多同时由额外线程读取的目录数，0 表示逐个扫描
+    #[clap(long, value_parser, default_value_t = 0)]
+    scan_concurrency: usize,
+    /// 把每项执行的操作写入 CSV 报告
+    #[clap(long, value_parser)]
+    report_csv: Option<String>,
+    /// 分析结果中显示源、目标的绝对路径，而不是相对路径
+    #[clap(long, value_parser)]
+    absolute_paths: bool,
+    /// 首次接管已有内容的目标目录：内容相同的文件只把修改时间对齐到源文件，不重新复制
+    #[clap(long, value_parser)]
+    prime: bool,
+    /// 不递归（recursive: false）时也在目标目录创建源目录下缺少的空子目录。
+    /// 递归时缺少的子目录（包括空目录）总会连同内容一起新增；不递归且不指定时只同步顶层文件，不创建子目录
+    #[clap(long, value_parser)]
+    include_empty_dirs: bool,
+    /// 递归时最多进入的目录层数，0 表示只处理根目录下的文件。更深的子目录不扫描，
+    /// 按不递归处理，其中的内容不更新、不删除；层数以内新增的目录仍连同全部内容一起复制
+    #[clap(long, value_parser)]
+    max_depth: Option<usize>,
+    /// 只比较在此之后修改过的源文件，可以是 `24h`、`7d` 这样的时长，也可以是 RFC 3339 时间，
+    /// 如 `2024-05-01T08:00:00+08:00`。其余源文件视为不存在，因此不删除目标目录中的任何内容
+    #[clap(long, value_parser, conflicts_with = "watch")]
+    since: Option<String>,
+    /// 边分析边执行，不预先展示完整的分析结果；不指定时先分析完再统一执行
+    #[clap(long, value_parser)]
+    pipeline: bool,
+    /// 执行每项删除前逐项确认：y 执行，n 跳过，a 之后的删除都不再询问，q 停止所有后续操作，
+    /// 其他输入视为跳过。不显示进度条
+    #[clap(long, value_parser)]
+    interactive: bool,
+    /// 配合 --interactive，执行每项更新前也逐项确认
+    #[clap(long, value_parser, requires = "interactive")]
+    interactive_updates: bool,
+    /// 只分析，把计划保存到指定文件，之后用 --apply-plan 执行
+    #[clap(long, value_parser, conflicts_with = "apply-plan")]
+    save_plan: Option<String>,
+    /// 执行 --save-plan 保存的计划，源或目标在保存后有变化时拒绝执行
+    #[clap(long, value_parser)]
+    apply_plan: Option<String>,
+    /// 只演练：照常分析并逐项列出将要执行的操作，但不改动任何文件
+    #[clap(long, value_parser)]
+    dry_run: bool,
+    /// 只统计两边的差异：打印分析结果和各类操作的数量后直接结束，不询问、不执行，也不改动目标目录
+    #[clap(long, value_parser, conflicts_with_all = &["save-plan", "apply-plan", "pipeline", "interactive", "watch"])]
+    stats_only: bool,
+    /// 不询问直接执行，结束时也不等待回车，用于计划任务、CI 等无人值守的场景
+    #[clap(short, long, value_parser)]
+    yes: bool,
+    /// 执行时逐行输出每项操作（便于写入日志文件），不指定时显示进度条
+    #[clap(short, long, value_parser)]
+    verbose: bool,
+    /// 某项操作失败时记录下来继续执行其余操作，结束时列出失败项并以非零退出码退出
+    #[clap(long, value_parser)]
+    keep_going: bool,
+    /// 只检查配置：解析配置、编译正则，检查源、目标目录是否存在，不扫描目录
+    #[clap(long, value_parser)]
+    config_check: bool,
+    /// 分析结果的输出格式，json 时只输出 JSON 格式的计划，不执行
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// 首次同步后持续监听源目录，有变化时自动重新同步，所有确认都视为同意
+    #[clap(long, value_parser)]
+    watch: bool,
+    /// 安静模式：不打印配置、逐项输出和进度条，分析结果只给出各类数量，错误照常输出
+    #[clap(short, long, value_parser, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// 开启 --exit-nonzero-on-noop 且无需同步时的退出码
+const NOOP_EXIT_CODE: u8 = 2;
+/// 开启 --detailed-exit-code 且有需要同步的内容时的退出码
+const CHANGED_EXIT_CODE: u8 = 3;
+/// 开启 --detailed-exit-code 且有操作失败时的退出码
+const FAILED_EXIT_CODE: u8 = 4;
+/// 开启 --detailed-exit-code 且在确认提示处取消时的退出码
+const DECLINED_EXIT_CODE: u8 = 5;
+
+/// 用户在确认提示处取消
+#[derive(Debug)]
+struct Declined;
+
+impl Display for Declined {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "已取消")
+    }
+}
+
+impl std::error::Error for Declined {}
+
+/// --keep-going 时收集到的失败操作数
+#[derive(Debug)]
+struct OperationsFailed(usize);
+
+impl Display for OperationsFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "有 {} 项操作失败", self.0)
+    }
+}
+
+impl std::error::Error for OperationsFailed {}
+
+#[derive(Debug, Clone, Default)]
+struct SyncPath {
+    /// 目录路径，可引用 `${VAR}`、`%VAR%` 形式的环境变量
+    path: String,
+    /// 白名单正则
+    include: Vec<Regex>,
+    /// 排除正则
+    exclude: Vec<Regex>,
+    /// 白名单 glob，匹配相对路径，与白名单正则任一命中即保留
+    include_glob: GlobSet,
+    /// 排除 glob，匹配相对路径
+    exclude_glob: GlobSet,
+    /// 只保留在该时长内被访问过的文件，如 `30d`
+    accessed_within: Option<Duration>,
+    /// 只保留不大于该大小的文件，如 `4G`
+    max_size: Option<u64>,
+    /// 只保留不小于该大小的文件
+    min_size: Option<u64>,
+    /// 只保留在该时长内修改过的文件，如 `30d`
+    modified_after: Option<Duration>,
+}
+
+/// 一组同步目录及其全部设置，由 [`read_config`] 从配置文件得到
+#[derive(Debug, Clone)]
+pub struct SyncContext {
+    /// 被同步目录信息
+    from: SyncPath,
+    /// 目标目录信息，路径以 `.zip` 结尾时同步到该压缩包内
+    to: SyncPath,
+    /// 是否递归子文件夹
+    recursive: bool,
+    /// 源目录根下 .ssyncignore 的规则（gitignore 语法，匹配相对路径），两侧命中的内容都不同步也不删除
+    ignore: Option<Gitignore>,
+    /// 复制大文件时向内核提供顺序读取/丢弃缓存的提示（仅 Linux 生效）
+    cache_hints: bool,
+    /// 同时执行新增、更新的线程数，默认 1 即逐个复制；删除始终逐个执行
+    threads: usize,
+    /// 是否同步 FIFO、设备等特殊文件。
+    /// 0.1.0 及之前的版本会把所有非目录项当作普通文件处理，现在默认只同步普通文件和目录，
+    /// 需要旧行为时设为 true
+    allow_special_files: bool,
+    /// 设置后，删除的文件会移入目标目录下的回收站（.ssync-trash）保留指定天数，
+    /// 每次运行开始时清理过期的内容。未设置时直接删除
+    trash_retention_days: Option<u64>,
+    /// 设置后，删除的文件移入该目录（不能在源、目标目录内），按批次保持相对路径，
+    /// 与目标目录不在同一个卷时复制过去再删除。同时设置 trash_retention_days 时按天数清理该目录。
+    /// 与 path 一样可引用环境变量
+    trash_dir: Option<PathBuf>,
+    /// 执行前的审批命令，计划以 JSON 形式写入其标准输入，退出码为 0 才继续执行
+    approve_command: Option<String>,
+    /// 确认执行后、开始文件操作前运行的命令，退出码不为 0 时取消执行
+    pre_hook: Option<String>,
+    /// 全部操作执行完毕后运行的命令，退出码不为 0 时报错。
+    /// 两个命令都可从环境变量 SSYNC_CHANGED_COUNT 取得本次的操作数，SSYNC_FROM、SSYNC_TO 取得两侧目录
+    post_hook: Option<String>,
+    /// 命令行指定的需要强制复制的相对路径
+    force_copy: Vec<PathBuf>,
+    /// 复制后同步只读、隐藏、系统、存档等文件属性（仅 Windows 生效）
+    preserve_attributes: bool,
+    /// 复制、改名后把访问时间和修改时间对齐到源文件（默认开启）。关闭后目标文件保留写入时的时间，
+    /// 两侧修改时间总是不同，大小相同的文件要比较内容才能判断是否需要更新
+    preserve_times: bool,
+    /// 扫描时不进入其他文件系统上的目录（挂载点；Windows 下为联接等重解析点）
+    one_file_system: bool,
+    /// 复制时先写入 `.ssync-part` 临时文件，中断后再次运行可从断点继续
+    resume_partial: bool,
+    /// 先复制到同目录下的 `.ssync-tmp` 临时文件，设置好修改时间等之后再改名为目标文件，
+    /// 其他程序不会读到写了一半的文件，中断时目标文件保持原样
+    atomic_write: bool,
+    /// 审计日志路径，每完成一项新增、删除、更新追加一行 JSON，记录时间、操作、相对路径，
+    /// 以及源文件和（覆盖、删除前的）目标文件的大小、修改时间。多次运行累积在同一个文件中
+    log_file: Option<PathBuf>,
+    /// 目标目录中永不删除的路径（相对目标目录的 glob），如 `uploads/**`
+    keep: GlobSet,
+    /// 是否删除目标目录中源目录没有的内容，设为 false 时只新增和更新（如给备份盘增量灌数据）
+    delete: bool,
+    /// 同步方向
+    mode: SyncMode,
+    /// 双向同步时上次同步的开始时间，两侧文件都在此之后修改过即为冲突；首次同步为 None
+    last_synced: Option<SystemTime>,
+    /// 在目标目录记录上次同步成功时的文件状态，据此区分一侧新增与另一侧删除、修改
+    state_db: bool,
+    /// state_db 中上次同步成功时的文件状态（按相对路径），未启用或首次同步时为 None
+    synced_files: Option<Arc<HashMap<String, SyncedFile>>>,
+    /// 整个目录新增后，核对源目录与目标目录的文件数量是否一致
+    verify_tree_counts: bool,
+    /// 按字节而不是文件数显示执行进度
+    progress_by_bytes: bool,
+    /// 复制、追加后重新读取目标文件，校验与源文件的哈希一致
+    verify: bool,
+    /// 复制时用外部命令转换匹配文件的内容
+    content_filter: Option<ContentFilter>,
+    /// 源、目标文件名只有大小写不同且内容相同时，把目标文件改名为源文件的大小写
+    fix_case: bool,
+    /// 按不区分大小写的文件名对应源、目标的文件和目录（Windows 上默认开启），
+    /// 只有大小写不同时视为同一个文件，不会产生一对新增和删除
+    case_insensitive: bool,
+    /// 新增、删除的文件中大小和哈希都相同的，在目标目录内移动，不再删除后重新复制
+    detect_renames: bool,
+    /// 每秒最多删除的项目数，避免大量删除时压垮繁忙的文件系统
+    delete_rate_limit: Option<f64>,
+    /// 复制、删除失败后的重试次数，等待时间从 RETRY_BASE_DELAY 开始每次翻倍
+    retries: u32,
+    /// 符号链接的处理方式
+    symlinks: SymlinkPolicy,
+    /// 目标目录剩余空间下限（字节），执行中低于该值时停止，避免写满磁盘
+    min_free_space: Option<u64>,
+    /// 复制的总带宽上限（每秒字节数），多线程复制时共用
+    max_bandwidth: Option<Arc<BandwidthLimiter>>,
+    /// 把内容相同但权限位不同的目标文件改为源文件的权限（仅 Unix 生效），关闭时只报告
+    preserve_permissions: bool,
+    /// 文件只会被追加（如日志）：目标不比源短时跳过，源更长时只追加新增的部分
+    append_only: bool,
+    /// 校验、比较内容时使用的哈希算法
+    hash_algo: HashAlgo,
+    /// 比较文件内容时的方式
+    compare: CompareMode,
+    /// 大小相同时总是比较内容；关闭时大小、修改时间都相同即认为相同，大小相同但修改时间不同就更新，不读取内容。
+    /// 也可写作 force_content_compare。两侧都有且大小相同的文件都要完整读取，耗时与数据量成正比，
+    /// 适合从备份恢复、修改时间不可信之后偶尔运行一次
+    deep_compare: bool,
+    /// 新增的目标路径长度上限，超出时跳过（如 Windows 未开启长路径时的 260）
+    max_path_length: Option<usize>,
+    /// 有路径超出 max_path_length 时直接报错，而不是跳过
+    long_path_error: bool,
+    /// 命令行 --prime：内容相同的文件只对齐修改时间，不复制
+    prime: bool,
+    /// 命令行 --include-empty-dirs：不递归时也创建源目录下的空子目录
+    include_empty_dirs: bool,
+    /// 命令行 --max-depth：递归时最多进入的目录层数，0 表示只有根目录
+    max_depth: Option<usize>,
+    /// 命令行 --since：修改时间早于该时间的源文件不参与比较
+    since: Option<SystemTime>,
+    /// 不删除本次运行开始后才在目标目录中出现的文件（可能由其他程序写入），目录中含有这样的文件时整个目录都不删除
+    protect_destination_created_during_run: bool,
+    /// 本次运行的开始时间
+    run_started: SystemTime,
+    /// 按源目录所在卷的 USN 日志只扫描上次同步后有变动的目录（仅 Windows 生效）。
+    /// 只能发现源目录的变动，目标目录被其他程序修改时需要关闭后完整同步一次
+    usn_journal: bool,
+    /// USN 日志中有变动的目录（相对源目录，含各级父目录），为 None 时完整扫描
+    changed_dirs: Option<Arc<HashSet<PathBuf>>>,
+    /// 分析、执行时读取文件属性、比较内容、复制和删除使用的文件系统
+    fs: Arc<dyn FileSystem>,
+}
+
+/// 比较文件内容的方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareMode {
+    /// 逐块比较两个文件的字节（默认）
+    MtimeThenBytes,
+    /// 比较两个文件的哈希（hash_algo），源文件的哈希只计算一次
+    Hash,
+    /// 完全不看修改时间，只按大小和哈希判断（compare: content），适合 FAT32 这样修改时间精度只有
+    /// 2 秒、复制后两侧时间总对不上的文件系统。大小相同的文件每次都要完整读取
+    Content,
+}
+
+/// 同步方向
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SyncMode {
+    /// 以源目录为准更新目标目录（默认）
+    ONEWAY,
+    /// 两侧都有的文件以修改时间较新的为准，只在一侧的文件复制到另一侧，不删除任何内容
+    BIDIRECTIONAL,
+}
+
+/// 与 state_db 中上次同步时的状态相比，文件的变化
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SyncedChange {
+    /// 上次同步时不存在
+    New,
+    Unchanged,
+    Changed,
+}
+
+/// 符号链接的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymlinkPolicy {
+    /// 跟随链接，按链接指向的文件/目录同步（默认，与旧版本一致），指向上层目录的链接跳过
+    FOLLOW,
+    /// 忽略符号链接
+    SKIP,
+    /// 在目标目录重建指向相同目标的链接
+    PRESERVE,
+}
+
+#[derive(Debug, Clone)]
+struct ContentFilter {
+    /// 匹配源文件相对路径的 glob
+    glob: GlobMatcher,
+    /// 转换命令，从标准输入读取源文件内容，标准输出即为目标文件内容
+    command: String,
+}
+
+impl ContentFilter {
+    /// 经命令转换后写入目标文件
+    fn copy(&self, src: &Path, dst: &Path) -> Result<()> {
+        let status = shell_command(&self.command)
+            .stdin(File::open(src)?)
+            .stdout(File::create(dst)?)
+            .status()?;
+        if !status.success() {
+            bail!("内容转换命令执行失败（{}）: {}", status, self.command);
+        }
+        fs::set_permissions(dst, fs::metadata(src)?.permissions())?;
+        Ok(())
+    }
+
+    /// 转换后的内容
+    fn output(&self, src: &Path) -> Result<Vec<u8>> {
+        let output = shell_command(&self.command)
+            .stdin(File::open(src)?)
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            bail!("内容转换命令执行失败（{}）: {}", output.status, self.command);
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// 目标目录下由 ssync 管理的回收站目录名，扫描时会被忽略
+const TRASH_DIR_NAME: &str = ".ssync-trash";
+/// 目标目录下保存 USN 日志读取位置的文件，扫描时会被忽略
+const USN_STATE_FILE_NAME: &str = ".ssync-usn.json";
+/// 目标目录下保存双向同步上次同步时间的文件，扫描时会被忽略
+const BIDIRECTIONAL_STATE_FILE_NAME: &str = ".ssync-bidirectional.json";
+/// 目标目录下保存 state_db 的文件，扫描时会被忽略
+const STATE_DB_FILE_NAME: &str = ".ssync-state.json";
+
+#[derive(Debug)]
+pub struct FileInfo {
+    /// 文件名或目录名
+    name: String,
+    /// 顶层目录路径
+    root: String,
+    /// 绝对路径（不含本文件/目录名）
+    absolute_dir: String,
+    /// 保留符号链接时，链接指向的目标
+    link_target: Option<PathBuf>,
+    /// 文件内容的哈希，首次计算后缓存
+    hash: OnceLock<Vec<u8>>,
+}
+
+impl FileInfo {
+    pub fn new(name: String, root: String, absolute_dir: String) -> Self {
+        Self {
+            name,
+            root,
+            absolute_dir,
+            link_target: None,
+            hash: OnceLock::new(),
+        }
+    }
+
+    pub fn absolute_dir_with_self(&self) -> String {
+        String::from(
+            Path::new(&self.absolute_dir).join(&self.name).as_path().to_str().unwrap()
+        )
+    }
+
+    fn file(&self, fs: &dyn FileSystem) -> Result<Box<dyn Read>> {
+        let path = self.absolute_dir_with_self();
+        fs.open(Path::new(&path)).with_context(|| format!("无法打开文件: {}", path))
+    }
+
+    pub fn relative_path(&self) -> String {
+        String::from(
+            pathdiff::diff_paths(self.absolute_dir_with_self(), &self.root).unwrap()
+                .as_path().to_str().unwrap()
+        )
+    }
+
+    fn relative_path_without_file(&self) -> String {
+        String::from(
+            pathdiff::diff_paths(self.absolute_dir_with_self(), &self.root).unwrap()
+                .as_path().parent().unwrap().to_str().unwrap()
+        )
+    }
+
+    fn to_path(&self) -> PathBuf {
+        Path::new(&self.absolute_dir_with_self()).to_path_buf()
+    }
+
+    /// 文件内容的哈希，同一个文件多次比较时只读取一次
+    fn content_hash(&self, fs: &dyn FileSystem, algo: HashAlgo) -> Result<&[u8]> {
+        if let Some(hash) = self.hash.get() {
+            return Ok(hash);
+        }
+        let hash = hash_prefix(&mut self.file(fs)?, u64::MAX, algo)
+            .with_context(|| format!("无法计算哈希: {}", self.absolute_dir_with_self()))?;
+        Ok(self.hash.get_or_init(|| hash))
+    }
+}
+
+#[derive(Debug)]
+pub struct DirectoryInfo {
+    /// 顶层目录路径
+    root: String,
+    /// 绝对路径（包含自己）
+    absolute_dir: String,
+    /// 子文件夹列表
+    sub_dirs: Vec<Arc<DirectoryInfo>>,
+    /// 文件列表
+    files: Vec<Arc<FileInfo>>,
+    /// 被 include、exclude 等规则过滤掉的条目：(相对路径, 命中的规则)
+    filtered: Vec<(String, String)>,
+}
+
+impl DirectoryInfo {
+    fn create(root: String, absolute_dir: String) -> Self {
+        Self {
+            root,
+            absolute_dir,
+            sub_dirs: Vec::new(),
+            files: Vec::new(),
+            filtered: Vec::new(),
+        }
+    }
+
+    /// 本目录及各级子目录中被过滤掉的条目
+    fn all_filtered(&self) -> Vec<&(String, String)> {
+        let mut filtered: Vec<_> = self.filtered.iter().collect();
+        for it in self.sub_dirs.iter() {
+            filtered.extend(it.all_filtered());
+        }
+        filtered
+    }
+
+    /// ancestors 为正在扫描的上层目录（规范化后的路径），用于发现跟随符号链接时形成的循环
+    fn load_all_file(absolute_path: String, recursive: bool,
+                     root_dir: String, context: &SyncContext,
+                     direction: &OperateDirection, limiter: &ScanLimiter,
+                     ancestors: &[PathBuf]) -> Result<DirectoryInfo> {
+        let fs = context.fs.as_ref();
+        // 解析符号链接后的实际位置，用于发现循环
+        let real_path = fs.canonicalize(Path::new(absolute_path.as_str()))?;
+        // 保证path为绝对路径。跟随符号链接进入的子目录保留链接所在的路径，否则相对路径会落到根目录之外
+        let path = if ancestors.is_empty() { real_path.clone() } else { PathBuf::from(&absolute_path) };
+        let ancestors = [ancestors, std::slice::from_ref(&real_path)].concat();
+        let absolute_path = path.to_str().unwrap().to_string();
+        let root_dir = fs.canonicalize(Path::new(root_dir.as_str()))?
+            .to_str().unwrap().to_string();
+        let mut directory_info = DirectoryInfo::create(root_dir.clone(), absolute_path);
+        if !fs.metadata(&path).is_ok_and(|stat| stat.is_dir) {
+            return Ok(directory_info);
+        }
+        assert!(!(recursive && root_dir.is_empty()), "root_dir can not be empty when recursive is true");
+        // 需要递归扫描的子目录，读完本目录后再统一处理
+        let mut pending_dirs = Vec::new();
+        for path in fs.read_dir(&path)? {
+            let abs_path = path.to_str().unwrap();
+            if let OperateDirection::TO = direction {
+                let file_name = path.file_name().unwrap();
+                let state_file = [USN_STATE_FILE_NAME, BIDIRECTIONAL_STATE_FILE_NAME, STATE_DB_FILE_NAME]
+                    .iter().any(|name| file_name == *name);
+                if (file_name == TRASH_DIR_NAME || state_file) && path.parent() == Some(Path::new(&root_dir)) {
+                    continue;
+                }
+                // 未完成的续传文件由复制流程接管
+                if context.resume_partial && abs_path.ends_with(PARTIAL_SUFFIX) {
+                    continue;
+                }
+                // atomic_write 中断时留下的临时文件，不当作目标目录独有的文件，再次复制时会先删除
+                if abs_path.ends_with(ATOMIC_TMP_SUFFIX) {
+                    continue;
+                }
+            }
+            if let Some(rule) = DirectoryInfo::_filtered_by(abs_path, &root_dir, context, direction) {
+                let relative_path = pathdiff::diff_paths(abs_path, &root_dir).unwrap();
+                directory_info.filtered.push((relative_path.to_str().unwrap().to_string(), rule));
+                continue;
+            }
+            if fs.symlink_metadata(&path)?.is_symlink {
+                // 指向的目标不存在的失效链接
+                let dangling = fs.metadata(&path).is_err();
+                match context.symlinks {
+                    SymlinkPolicy::FOLLOW => if dangling {
+                        bail!("符号链接指向的目标不存在: {} -> {}", abs_path, fs.read_link(&path)?.display());
+                    } else if ancestors.contains(&fs.canonicalize(&path)?) {
+                        println!("警告: 跳过指向上层目录、会形成循环的符号链接: {} -> {}",
+                                 abs_path, fs.read_link(&path)?.display());
+                        continue;
+                    }
+                    SymlinkPolicy::SKIP => {
+                        if dangling {
+                            println!("警告: 跳过失效的符号链接: {}", abs_path);
+                        } else {
+                            println!("跳过符号链接: {}", abs_path);
+                        }
+                        continue;
+                    }
+                    SymlinkPolicy::PRESERVE => {
+                        // 链接本身作为一个文件处理，不进入其指向的目录，失效的链接也原样重建
+                        let mut file_info = FileInfo::new(
+                            path.file_name().unwrap().to_str().unwrap().to_string(),
+                            root_dir.clone(),
+                            path.parent().unwrap().to_str().unwrap().to_string(),
+                        );
+                        file_info.link_target = Some(fs.read_link(&path)?);
+                        directory_info.files.push(Arc::new(file_info));
+                        continue;
+                    }
+                }
+            }
+            let stat = fs.metadata(&path)?;
+            if stat.is_file && !(DirectoryInfo::_check_accessed_within(&path, &stat, context.sync_path(direction))
+                && DirectoryInfo::_check_size_and_modified(&stat, context.sync_path(direction))) {
+                continue;
+            }
+            if let (OperateDirection::FROM, Some(since)) = (direction, context.since) {
+                if stat.is_file && stat.modified < since {
+                    continue;
+                }
+            }
+            if stat.is_dir {
+                if context.one_file_system && is_on_other_file_system(&path, Path::new(&root_dir))? {
+                    println!("跳过其他文件系统上的目录: {}", abs_path);
+                    continue;
+                }
+                let unchanged = context.changed_dirs.as_ref().is_some_and(|dirs| {
+                    !dirs.contains(path.strip_prefix(&root_dir).unwrap())
+                });
+                if recursive && !unchanged && context.within_max_depth(ancestors.len() - 1) {
+                    pending_dirs.push(abs_path.to_string());
+                } else {
+                    // 不递归、超过 max_depth 或 USN 日志中没有变动的目录不进入，只记录目录本身
+                    let dict_info = DirectoryInfo::create(root_dir.clone(), abs_path.to_string());
+                    directory_info.sub_dirs.push(Arc::new(dict_info));
+                }
+            } else if stat.is_file || context.allow_special_files {
+                let file_info = FileInfo::new(
+                    path.file_name().unwrap().to_str().unwrap().to_string(),
+                    root_dir.clone(),
+                    path.parent().unwrap().to_str().unwrap().to_string(),
+                );
+                directory_info.files.push(Arc::new(file_info));
+            } else {
+                // FIFO、设备、套接字等特殊文件，复制时可能一直阻塞
+                println!("跳过特殊文件: {}", abs_path);
+            }
+        }
+
+        // 有空闲名额时子目录交给新线程扫描，否则在当前线程扫描，同时扫描的目录数不超过上限
+        let load_sub_dir = |dir: String| DirectoryInfo::load_all_file(
+            dir, recursive, root_dir.clone(), context, direction, limiter, &ancestors,
+        );
+        let sub_dirs = thread::scope(|scope| {
+            let mut handles = Vec::new();
+            let mut results = Vec::new();
+            for dir in pending_dirs {
+                if limiter.try_acquire() {
+                    handles.push((results.len(), scope.spawn(move || {
+                        let result = load_sub_dir(dir);
+                        limiter.release();
+                        result
+                    })));
+                    results.push(None);
+                } else {
+                    results.push(Some(load_sub_dir(dir)));
+                }
+            }
+            for (index, handle) in handles {
+                results[index] = Some(handle.join().unwrap());
+            }
+            results.into_iter().map(Option::unwrap).collect::<Result<Vec<_>>>()
+        })?;
+        directory_info.sub_dirs.extend(sub_dirs.into_iter().map(Arc::new));
+
+        Ok(directory_info)
+    }
+
+    /// 正则同时匹配绝对路径和相对根目录的路径（以 `/` 分隔），如 `^target$` 可直接排除顶层的 target 目录；
+    /// glob 和 .ssyncignore 只匹配相对路径，如 `*.tmp`。
+    /// 目录在这里被排除时不会再进入，整个子树都不读取
+    fn _check_include_and_exclude(abs_path: &str,
+                                  root_dir: &str,
+                                  context: &SyncContext,
+                                  direction: &OperateDirection) -> bool {
+        DirectoryInfo::_filtered_by(abs_path, root_dir, context, direction).is_none()
+    }
+
+    /// 条目被过滤掉时返回命中的规则，用于展示
+    fn _filtered_by(abs_path: &str,
+                    root_dir: &str,
+                    context: &SyncContext,
+                    direction: &OperateDirection) -> Option<String> {
+        let sync_path = context.sync_path(direction);
+        let relative_path = pathdiff::diff_paths(abs_path, root_dir).unwrap()
+            .to_str().unwrap().replace('\\', "/");
+        let is_match = |reg: &Regex| reg.is_match(abs_path) || reg.is_match(&relative_path);
+        if sync_path.include.iter().any(is_match) || sync_path.include_glob.is_match(&relative_path) {
+            return None;
+        }
+        if !sync_path.include.is_empty() || !sync_path.include_glob.is_empty() {
+            return Some(String::from("不匹配任何 include"));
+        }
+        if let Some(ignore) = &context.ignore {
+            let is_dir = context.fs.metadata(Path::new(abs_path)).is_ok_and(|stat| stat.is_dir);
+            if let ignore::Match::Ignore(glob) = ignore.matched(&relative_path, is_dir) {
+                return Some(format!("{}: {}", IGNORE_FILE_NAME, glob.original()));
+            }
+        }
+        if let Some(reg) = sync_path.exclude.iter().find(|reg| is_match(reg)) {
+            return Some(format!("exclude: {}", reg.as_str()));
+        }
+        sync_path.exclude_glob.matches(&relative_path).first()
+            .map(|index| format!("exclude_glob[{}]", index))
+    }
+
+    /// 按最后访问时间过滤文件。
+    /// 挂载为 noatime 等情况下访问时间不会更新，此时结果不可靠，只提示一次
+    fn _check_accessed_within(path: &Path, stat: &FileStat, sync_path: &SyncPath) -> bool {
+        static ATIME_WARNED: AtomicBool = AtomicBool::new(false);
+        let accessed_within = match sync_path.accessed_within {
+            Some(accessed_within) => accessed_within,
+            None => return true,
+        };
+        let accessed = match stat.accessed {
+            Some(accessed) => accessed,
+            None => {
+                if !ATIME_WARNED.swap(true, Ordering::Relaxed) {
+                    println!("警告: 当前平台无法获取文件访问时间，accessed_within 不生效");
+                }
+                return true;
+            }
+        };
+        if accessed < stat.modified && !ATIME_WARNED.swap(true, Ordering::Relaxed) {
+            println!("警告: {} 的访问时间早于修改时间，所在挂载点可能未记录访问时间（noatime），\
+                      accessed_within 的过滤结果可能不准确", path.display());
+        }
+        match SystemTime::now().duration_since(accessed) {
+            Ok(elapsed) => elapsed <= accessed_within,
+            // 访问时间在未来，视为最近访问过
+            Err(_) => true,
+        }
+    }
+
+    /// 按 max_size、min_size、modified_after 过滤文件
+    fn _check_size_and_modified(stat: &FileStat, sync_path: &SyncPath) -> bool {
+        if sync_path.max_size.is_some_and(|max_size| stat.len > max_size)
+            || sync_path.min_size.is_some_and(|min_size| stat.len < min_size) {
+            return false;
+        }
+        match sync_path.modified_after {
+            // 修改时间在未来，视为最近修改过
+            Some(modified_after) => SystemTime::now().duration_since(stat.modified)
+                .map_or(true, |elapsed| elapsed <= modified_after),
+            None => true,
+        }
+    }
+
+    fn name(&self) -> String {
+        String::from(
+            Path::new(&self.absolute_dir).file_name().unwrap().to_str().unwrap()
+        )
+    }
+
+    /// 目录下最长的文件/子目录路径相对本目录多出的长度
+    fn longest_descendant_len(&self) -> usize {
+        let own_len = path_length(&self.absolute_dir);
+        let files = self.files.iter()
+            .map(|it| path_length(&it.absolute_dir_with_self()) - own_len);
+        let sub_dirs = self.sub_dirs.iter()
+            .map(|it| path_length(&it.absolute_dir) - own_len + it.longest_descendant_len());
+        files.chain(sub_dirs).max().unwrap_or(0)
+    }
+
+    fn to_file_info(&self) -> FileInfo {
+        FileInfo::new(
+            self.name().clone(),
+            self.root.clone(),
+            Path::new(&self.absolute_dir).parent().unwrap().to_str().unwrap().to_string(),
+        )
+    }
+
+    pub fn relative_path(&self) -> String {
+        String::from(
+            pathdiff::diff_paths(&self.absolute_dir, &self.root).unwrap()
+                .as_path().to_str().unwrap()
+        )
+    }
+}
+
+enum OperateDirection {
+    FROM,
+    TO,
+}
+
+impl SyncContext {
+    /// 换成其他的文件系统实现，如测试时使用内存中的文件
+    pub fn with_file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    /// 第 depth 层（根目录为 0）目录的子目录是否在 max_depth 以内
+    fn within_max_depth(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth < max_depth)
+    }
+
+    /// 删除时移入的回收站目录，为 None 时直接删除
+    fn trash_root(&self) -> Option<PathBuf> {
+        match &self.trash_dir {
+            Some(trash_dir) => Some(trash_dir.clone()),
+            None => self.trash_retention_days.map(|_| Path::new(&self.to.path).join(TRASH_DIR_NAME)),
+        }
+    }
+
+    /// 对应源、目标文件时使用的文件名，case_insensitive 时不区分大小写
+    fn name_key(&self, name: &str) -> String {
+        if self.case_insensitive { name.to_lowercase() } else { name.to_string() }
+    }
+
+    fn sync_path(&self, direction: &OperateDirection) -> &SyncPath {
+        match direction {
+            OperateDirection::FROM => &self.from,
+            OperateDirection::TO => &self.to,
+        }
+    }
+
+    /// 源文件（绝对路径）需要转换内容时，返回对应的转换配置
+    fn content_filter_for(&self, src: &Path) -> Option<&ContentFilter> {
+        let filter = self.content_filter.as_ref()?;
+        let root = self.fs.canonicalize(Path::new(&self.from.path)).ok()?;
+        let relative_path = src.strip_prefix(root).ok()?;
+        filter.glob.is_match(relative_path).then_some(filter)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum FileAction {
+    ADD,
+    DEL,
+    UPDATE,
+    /// 命中 keep 而不删除，只用于展示
+    KEEP,
+    /// 目标文件改名为源文件的名字
+    RENAME,
+    /// 目标文件的权限与源文件不同
+    CHMOD,
+    /// 目标路径过长而跳过，只用于展示
+    SKIP,
+    /// 内容相同，把目标文件的修改时间对齐到源文件
+    TOUCH,
+    /// 双向同步时两侧都在上次同步后修改过，不处理，只用于展示
+    CONFLICT,
+}
+
+#[derive(Debug)]
+pub struct DecisionResultItem {
+    pub action: FileAction,
+    // 操作为删除时，没有src
+    pub src_file_info: Option<Arc<FileInfo>>,
+    pub dest_file_info: Arc<FileInfo>,
+    // 操作为更新时，记录判定为更新的原因
+    pub reason: Option<UpdateReason>,
+}
+
+impl DecisionResultItem {
+    /// 保留的符号链接指向的目标，删除时取目标目录中的链接
+    fn link_target(&self) -> Option<&PathBuf> {
+        match &self.src_file_info {
+            Some(src) => src.link_target.as_ref(),
+            None => self.dest_file_info.link_target.as_ref(),
+        }
+    }
+
+    /// 改名、移动后的目标路径：目标根目录下与源文件相同的相对路径
+    fn rename_target(&self) -> PathBuf {
+        Path::new(&self.dest_file_info.root).join(self.src_file_info.as_ref().unwrap().relative_path())
+    }
+}
+
+/// 判定文件需要更新的原因
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateReason {
+    /// 大小不同
+    SizeDiffers,
+    /// 大小相同但内容不同
+    ContentDiffers,
+    /// 大小相同，修改时间不同，未比较内容
+    MtimeDiffers,
+    /// 大小相同，哈希（compare: hash、content，或压缩包中的 CRC）不同
+    HashDiffers,
+    /// 命令行要求强制复制
+    Forced,
+    /// 符号链接指向的目标不同
+    LinkTargetDiffers,
+    /// 内容相同，权限位不同
+    PermissionDiffers,
+    /// 只追加模式下源文件有新追加的内容
+    Appended,
+    /// 双向同步时目标文件较新，复制回源目录
+    NewerInDestination,
+    /// 双向同步时只在目标目录中，复制回源目录
+    OnlyInDestination,
+}
+
+impl UpdateReason {
+    const ALL: [UpdateReason; 10] = [
+        UpdateReason::SizeDiffers,
+        UpdateReason::ContentDiffers,
+        UpdateReason::MtimeDiffers,
+        UpdateReason::HashDiffers,
+        UpdateReason::Forced,
+        UpdateReason::LinkTargetDiffers,
+        UpdateReason::PermissionDiffers,
+        UpdateReason::Appended,
+        UpdateReason::NewerInDestination,
+        UpdateReason::OnlyInDestination,
+    ];
+
+    /// 从展示文字还原，用于读取保存的计划
+    fn parse(text: &str) -> Option<UpdateReason> {
+        UpdateReason::ALL.into_iter().find(|reason| reason.to_string() == text)
+    }
+}
+
+impl Display for UpdateReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateReason::SizeDiffers => write!(f, "大小不同"),
+            UpdateReason::ContentDiffers => write!(f, "内容不同"),
+            UpdateReason::MtimeDiffers => write!(f, "修改时间不同"),
+            UpdateReason::HashDiffers => write!(f, "哈希不同"),
+            UpdateReason::Forced => write!(f, "强制复制"),
+            UpdateReason::LinkTargetDiffers => write!(f, "链接目标不同"),
+            UpdateReason::PermissionDiffers => write!(f, "权限不同"),
+            UpdateReason::Appended => write!(f, "追加内容"),
+            UpdateReason::NewerInDestination => write!(f, "目标较新，复制回源目录"),
+            UpdateReason::OnlyInDestination => write!(f, "只在目标目录中，复制回源目录"),
+        }
+    }
+}
+
+/// 分析结果，各类操作按所在目录（相对源根目录的路径）分组
+#[derive(Debug)]
+pub struct DecisionResult {
+    pub add_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub del_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub update_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub keep_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub rename_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub permission_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub skipped_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub touch_items: HashMap<String, Vec<DecisionResultItem>>,
+    pub conflict_items: HashMap<String, Vec<DecisionResultItem>>,
+    /// 新增与更新需要复制的 (总字节数, 文件数)，首次用到时统计，执行时不再重复读取
+    _transfer_size: OnceLock<(u64, usize)>,
+    /// 是否修正权限不同的文件（preserve_permissions），否则这些文件只报告，不计入需要执行的操作
+    _apply_permissions: bool,
+    /// 统计大小时读取的文件系统
+    _fs: Arc<dyn FileSystem>,
+}
+
+impl DecisionResult {
+    fn new() -> Self {
+        Self {
+            add_items: HashMap::new(),
+            del_items: HashMap::new(),
+            update_items: HashMap::new(),
+            keep_items: HashMap::new(),
+            rename_items: HashMap::new(),
+            permission_items: HashMap::new(),
+            skipped_items: HashMap::new(),
+            touch_items: HashMap::new(),
+            conflict_items: HashMap::new(),
+            _transfer_size: OnceLock::new(),
+            _apply_permissions: false,
+            _fs: Arc::new(RealFileSystem),
+        }
+    }
+
+    /// 按配置决定权限不同的文件是否需要执行，统计大小时读取配置的文件系统
+    fn for_context(context: &SyncContext) -> Self {
+        Self {
+            _apply_permissions: context.preserve_permissions,
+            _fs: context.fs.clone(),
+            ..Self::new()
+        }
+    }
+
+    pub fn total_count(&self) -> usize {
+        let mut cnt = 0;
+        self.add_items.values().for_each(|x| cnt += x.len());
+        self.del_items.values().for_each(|x| cnt += x.len());
+        self.update_items.values().for_each(|x| cnt += x.len());
+        self.rename_items.values().for_each(|x| cnt += x.len());
+        if self._apply_permissions {
+            self.permission_items.values().for_each(|x| cnt += x.len());
+        }
+        self.touch_items.values().for_each(|x| cnt += x.len());
+        cnt
+    }
+
+    /// 各类操作的数量，--stats-only 时显示
+    fn counts_summary(&self) -> String {
+        let count = |items: &HashMap<String, Vec<DecisionResultItem>>| items.values().map(Vec::len).sum::<usize>();
+        format!("· 统计：新增 {} 项，删除 {} 项，更新 {} 项，改名 {} 项，权限不同 {} 项，对齐时间 {} 项，冲突 {} 项",
+                count(&self.add_items), count(&self.del_items), count(&self.update_items),
+                count(&self.rename_items), count(&self.permission_items), count(&self.touch_items),
+                count(&self.conflict_items))
+    }
+
+    pub fn summary(&self) -> String {
+        self.summary_with_options(&SummaryOptions::default())
+    }
+
+    /// 按选项生成分析结果摘要
+    fn summary_with_options(&self, options: &SummaryOptions) -> String {
+        // 只报告权限不同时同样列出
+        let has_permission_items = self.permission_items.values().any(|items| !items.is_empty());
+        if self.is_empty() && !self.has_skipped() && !self.has_conflicts() && !has_permission_items {
+            return String::from("无任务需执行");
+        }
+        let mut summary = String::new();
+
+        fn print_func(items: &HashMap<String, Vec<DecisionResultItem>>, options: &SummaryOptions,
+                      summary: &mut String) {
+            let total: usize = items.values().map(|it| it.len()).sum();
+            if total == 0 {
+                summary.pop();
+                summary.push_str("无\n");
+                return;
+            }
+            let shown = options.max_lines.map_or(total, |max_lines| max_lines.min(total));
+            // HashMap 的遍历顺序每次运行都不同，按相对路径排序后输出，便于对比多次运行的结果
+            let mut sorted: Vec<_> = items.values().flatten().collect();
+            sorted.sort_by_cached_key(|item| item.dest_file_info.relative_path());
+            for item in sorted.into_iter().take(shown) {
+                summary.push('\t');
+                if options.absolute_paths {
+                    let dest = adjust_canonicalization(item.dest_file_info.absolute_dir_with_self());
+                    match (&item.action, &item.src_file_info) {
+                        (FileAction::ADD | FileAction::UPDATE, Some(src)) => summary.push_str(&format!(
+                            "{} => {}", adjust_canonicalization(src.absolute_dir_with_self()), dest,
+                        )),
+                        _ => summary.push_str(&dest),
+                    }
+                } else {
+                    summary.push_str(&item.dest_file_info.relative_path());
+                }
+                if let FileAction::RENAME = item.action {
+                    summary.push_str(&format!(" -> {}", item.src_file_info.as_ref().unwrap().relative_path()));
+                } else if let Some(target) = item.link_target() {
+                    summary.push_str(&format!(" -> {}", target.display()));
+                }
+                if let Some(reason) = &item.reason {
+                    summary.push_str(&format!(" [{}]", reason));
+                }
+                summary.push('\n');
+            }
+            if shown == 0 {
+                summary.push_str(&format!("\t共 {} 项\n", total));
+            } else if shown < total {
+                summary.push_str(&format!("\t...以及其余 {} 项\n", total - shown));
+            }
+        }
+
+        summary.push_str("——分析结果——\n");
+        summary.push_str("· 新增：\n");
+        print_func(&self.add_items, options, &mut summary);
+
+        if options.deletions_disabled {
+            summary.push_str("· 删除：已关闭（delete: false 或 --since），目标目录中多出的内容不会删除\n");
+        } else {
+            summary.push_str("· 删除：\n");
+            print_func(&self.del_items, options, &mut summary);
+        }
+
+        summary.push_str("· 更新：\n");
+        print_func(&self.update_items, options, &mut summary);
+
+        if self.rename_items.values().any(|items| !items.is_empty()) {
+            summary.push_str("· 改名/移动：\n");
+            print_func(&self.rename_items, options, &mut summary);
+        }
+
+        if self.permission_items.values().any(|items| !items.is_empty()) {
+            summary.push_str("· 权限不同：\n");
+            print_func(&self.permission_items, options, &mut summary);
+        }
+
+        if self.touch_items.values().any(|items| !items.is_empty()) {
+            summary.push_str("· 对齐修改时间：\n");
+            print_func(&self.touch_items, options, &mut summary);
+        }
+
+        if self.keep_items.values().any(|items| !items.is_empty()) {
+            summary.push_str("· 保留：\n");
+            print_func(&self.keep_items, options, &mut summary);
+        }
+
+        if self.has_skipped() {
+            summary.push_str("· 跳过(路径过长)：\n");
+            print_func(&self.skipped_items, options, &mut summary);
+        }
+
+        if self.has_conflicts() {
+            summary.push_str("· 冲突（两边都有修改，未处理）：\n");
+            print_func(&self.conflict_items, options, &mut summary);
+        }
+
+        let (added, freed, changed) = self.storage_impact();
+        summary.push_str(&format!("· 空间变化：新增 {}，释放 {}，更新 {}，合计 {}\n",
+                                  format_bytes(added), format_bytes(freed),
+                                  format_signed_bytes(changed),
+                                  format_signed_bytes(added as i64 - freed as i64 + changed)));
+        let (transfer_bytes, transfer_files) = self.transfer_size();
+        summary.push_str(&format!("· 需传输：{}，共 {} 个文件\n", format_bytes(transfer_bytes), transfer_files));
+
+        summary
+    }
+
+    /// 执行后目标目录的空间变化：(新增文件的字节数, 删除释放的字节数, 更新前后的大小差)，
+    /// 读取不到大小的按 0 计
+    fn storage_impact(&self) -> (u64, u64, i64) {
+        let size = |info: &FileInfo| path_size(self._fs.as_ref(), &info.to_path()).unwrap_or(0);
+        let added = self.add_items.values().flatten()
+            .filter_map(|item| item.src_file_info.as_deref())
+            .map(size)
+            .sum();
+        let freed = self.del_items.values().flatten()
+            .map(|item| size(&item.dest_file_info))
+            .sum();
+        let changed = self.update_items.values().flatten()
+            .map(|item| {
+                let src = item.src_file_info.as_deref().map_or(0, size);
+                src as i64 - size(&item.dest_file_info) as i64
+            })
+            .sum();
+        (added, freed, changed)
+    }
+
+    fn merge(&mut self, other: DecisionResult) {
+        self.add_items.extend(other.add_items);
+        self.del_items.extend(other.del_items);
+        self.update_items.extend(other.update_items);
+        self.keep_items.extend(other.keep_items);
+        self.rename_items.extend(other.rename_items);
+        self.permission_items.extend(other.permission_items);
+        self.skipped_items.extend(other.skipped_items);
+        self.touch_items.extend(other.touch_items);
+        self.conflict_items.extend(other.conflict_items);
+        self._transfer_size = OnceLock::new();
+        self._apply_permissions |= other._apply_permissions;
+    }
+
+    /// detect_renames：把内容相同的一对新增、删除文件换成在目标目录内移动。
+    /// 先按大小配对，大小相同再比较哈希；整体新增、删除的目录不参与
+    fn detect_renames(&mut self, fs: &dyn FileSystem, algo: HashAlgo) -> Result<()> {
+        self._transfer_size = OnceLock::new();
+        let is_plain_file = |info: &FileInfo| info.link_target.is_none()
+            && fs.metadata(&info.to_path()).is_ok_and(|stat| stat.is_file);
+        let mut deleted_by_size: HashMap<u64, Vec<DecisionResultItem>> = HashMap::new();
+        let mut del_items = Vec::new();
+        for item in self.del_items.drain().flat_map(|(_, items)| items) {
+            if is_plain_file(&item.dest_file_info) {
+                let size = fs.metadata(&item.dest_file_info.to_path())?.len;
+                deleted_by_size.entry(size).or_default().push(item);
+            } else {
+                del_items.push(item);
+            }
+        }
+
+        let mut add_items = Vec::new();
+        for item in self.add_items.drain().flat_map(|(_, items)| items) {
+            let src = item.src_file_info.clone().unwrap();
+            let mut matched = None;
+            if is_plain_file(&src) {
+                if let Some(candidates) = deleted_by_size.get_mut(&fs.metadata(&src.to_path())?.len) {
+                    for (i, candidate) in candidates.iter().enumerate() {
+                        if candidate.dest_file_info.content_hash(fs, algo)? == src.content_hash(fs, algo)? {
+                            matched = Some(candidates.swap_remove(i));
+                            break;
+                        }
+                    }
+                }
+            }
+            match matched {
+                Some(deleted) => self.rename_items.entry(src.relative_path_without_file()).or_default()
+                    .push(DecisionResultItem {
+                        action: FileAction::RENAME,
+                        src_file_info: Some(src),
+                        dest_file_info: deleted.dest_file_info,
+                        reason: None,
+                    }),
+                None => add_items.push(item),
+            }
+        }
+
+        for item in add_items {
+            self.add_items.entry(item.dest_file_info.relative_path_without_file()).or_default().push(item);
+        }
+        for item in del_items.into_iter().chain(deleted_by_size.into_values().flatten()) {
+            self.del_items.entry(item.dest_file_info.relative_path_without_file()).or_default().push(item);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total_count() == 0
+    }
+
+    fn has_skipped(&self) -> bool {
+        self.skipped_items.values().any(|items| !items.is_empty())
+    }
+
+    fn has_conflicts(&self) -> bool {
+        self.conflict_items.values().any(|items| !items.is_empty())
+    }
+
+    /// 冲突文件的相对路径
+    fn conflict_paths(&self) -> HashSet<String> {
+        self.conflict_items.values().flatten()
+            .map(|item| item.dest_file_info.relative_path())
+            .collect()
+    }
+
+    /// 新增与更新需要复制的总字节数，读取不到大小的按 0 计
+    fn total_bytes(&self) -> u64 {
+        self.transfer_size().0
+    }
+
+    /// 新增与更新需要复制的 (总字节数, 文件数)，目录按其中的文件统计，读取不到的按 0 计
+    fn transfer_size(&self) -> (u64, usize) {
+        *self._transfer_size.get_or_init(|| {
+            self.add_items.values()
+                .chain(self.update_items.values())
+                .flatten()
+                .filter_map(|item| item.src_file_info.as_ref())
+                .map(|src| path_size_and_count(self._fs.as_ref(), &src.to_path()).unwrap_or((0, 0)))
+                .fold((0, 0), |(bytes, files), (size, count)| (bytes + size, files + count))
+        })
+    }
+
+    fn plan_items(&self) -> Vec<PlanItem> {
+        self.add_items.values()
+            .chain(self.del_items.values())
+            .chain(self.update_items.values())
+            .chain(self.rename_items.values())
+            .chain(self.permission_items.values())
+            .chain(self.touch_items.values())
+            .chain(self.conflict_items.values())
+            .flatten()
+            .map(|item| PlanItem {
+                action: item.action,
+                relative_path: item.dest_file_info.relative_path(),
+                source: item.src_file_info.as_ref().map(|it| it.absolute_dir_with_self()),
+                destination: item.dest_file_info.absolute_dir_with_self(),
+                reason: item.reason.map(|reason| reason.to_string()),
+                link_target: item.link_target().map(|target| target.display().to_string()),
+                source_size: None,
+                source_hash: None,
+                destination_size: None,
+                destination_hash: None,
+            })
+            .collect()
+    }
+
+    /// 以 JSON 输出计划，附带源文件（目录为其中文件的总和）的大小，供外部工具使用
+    pub fn to_json(&self) -> Result<String> {
+        let mut items = self.plan_items();
+        for item in items.iter_mut() {
+            if let Some(source) = &item.source {
+                item.source_size = path_size(self._fs.as_ref(), Path::new(source)).ok();
+            }
+        }
+        Ok(serde_json::to_string_pretty(&Plan { from: None, to: None, items })?)
+    }
+
+    /// 生成供 --apply-plan 执行的计划，附带源、目标当前的大小和源文件哈希，执行前据此检查是否有变化
+    fn to_saved_plan(&self, context: &SyncContext) -> Result<String> {
+        let fs = context.fs.as_ref();
+        let is_file = |path: &Path| fs.metadata(path).is_ok_and(|stat| stat.is_file);
+        let mut items = self.plan_items();
+        for item in items.iter_mut() {
+            if let Some(source) = &item.source {
+                let source = Path::new(source);
+                item.source_size = Some(path_size(fs, source)?);
+                if is_file(source) {
+                    item.source_hash = Some(to_hex(&hash_file(fs, source, context.hash_algo)?));
+                }
+            }
+            let destination = Path::new(&item.destination);
+            item.destination_size = path_size(fs, destination).ok();
+            if is_file(destination) {
+                item.destination_hash = Some(to_hex(&hash_file(fs, destination, context.hash_algo)?));
+            }
+        }
+        Ok(serde_json::to_string_pretty(&Plan {
+            from: Some(context.from.path.clone()),
+            to: Some(context.to.path.clone()),
+            items,
+        })?)
+    }
+
+    /// 读取保存的计划。源、目标在保存之后有变化时报错，列出有变化的条目
+    fn from_saved_plan(text: &str, context: &SyncContext) -> Result<DecisionResult> {
+        let plan: Plan = serde_json::from_str(text)?;
+        let (from, to) = match (plan.from, plan.to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => bail!("计划文件缺少源目录或目标目录"),
+        };
+        if from != context.from.path || to != context.to.path {
+            bail!("计划文件的目录（{} -> {}）与当前配置（{} -> {}）不一致",
+                  from, to, context.from.path, context.to.path);
+        }
+
+        // 计划中是扫描得到的绝对路径，相对路径要相对规范化后的根目录计算
+        let from = fs::canonicalize(&from).with_context(|| format!("源目录不存在: {}", from))?;
+        let to = canonicalize_lenient(&to)?;
+        let (from, to) = (from.to_str().unwrap(), to.to_str().unwrap());
+        let mut drifted = Vec::new();
+        let mut decision_result = DecisionResult::for_context(context);
+        for item in plan.items {
+            if let Some(drift) = item.drift(context.fs.as_ref(), context.hash_algo)? {
+                drifted.push(format!("\t{} [{}]", item.relative_path, drift));
+                continue;
+            }
+            let file_info = |path: &str, root: &str| {
+                let path = Path::new(path);
+                FileInfo::new(
+                    path.file_name().unwrap().to_str().unwrap().to_string(),
+                    root.to_string(),
+                    path.parent().unwrap().to_str().unwrap().to_string(),
+                )
+            };
+            let dest_file_info = file_info(&item.destination, to);
+            let src_file_info = item.source.as_ref().map(|source| {
+                let mut src_file_info = file_info(source, from);
+                src_file_info.link_target = item.link_target.as_ref().map(PathBuf::from);
+                Arc::new(src_file_info)
+            });
+            let items = match item.action {
+                FileAction::ADD => &mut decision_result.add_items,
+                FileAction::DEL => &mut decision_result.del_items,
+                FileAction::UPDATE => &mut decision_result.update_items,
+                FileAction::RENAME => &mut decision_result.rename_items,
+                FileAction::CHMOD => &mut decision_result.permission_items,
+                FileAction::TOUCH => &mut decision_result.touch_items,
+                FileAction::KEEP | FileAction::SKIP | FileAction::CONFLICT => continue,
+            };
+            items.entry(dest_file_info.relative_path_without_file()).or_default().push(DecisionResultItem {
+                action: item.action,
+                src_file_info,
+                dest_file_info: Arc::new(dest_file_info),
+                reason: item.reason.as_deref().and_then(UpdateReason::parse),
+            });
+        }
+        if !drifted.is_empty() {
+            bail!("以下条目在保存计划后发生了变化，请重新生成计划:\n{}", drifted.join("\n"));
+        }
+        Ok(decision_result)
+    }
+}
+
+/// 分析结果摘要的展示选项
+#[derive(Debug, Default)]
+struct SummaryOptions {
+    /// 每类操作最多列出的条目数，超出部分只给出数量
+    max_lines: Option<usize>,
+    /// 显示完整的绝对路径，新增和更新同时显示源路径
+    absolute_paths: bool,
+    /// 配置了 delete: false，删除一栏注明已关闭
+    deletions_disabled: bool,
+}
+
+/// 计划的 JSON 结构
+#[derive(Serialize, Deserialize)]
+struct Plan {
+    /// 保存计划时的源目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    /// 保存计划时的目标目录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    items: Vec<PlanItem>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlanItem {
+    action: FileAction,
+    /// 相对目标根目录的路径
+    relative_path: String,
+    /// 源文件绝对路径，删除时为空
+    source: Option<String>,
+    /// 目标文件绝对路径
+    destination: String,
+    /// 更新的原因
+    reason: Option<String>,
+    /// 保留的符号链接指向的目标
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link_target: Option<String>,
+    /// 保存计划时源文件（目录为其中文件的总和）的大小
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_size: Option<u64>,
+    /// 保存计划时源文件的哈希
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_hash: Option<String>,
+    /// 保存计划时目标文件的大小，当时不存在则为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    destination_size: Option<u64>,
+    /// 保存计划时目标文件的哈希，大小不变的修改也能发现
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    destination_hash: Option<String>,
+}
+
+impl PlanItem {
+    /// 与保存计划时相比的变化，没有变化时返回 None
+    fn drift(&self, fs: &dyn FileSystem, algo: HashAlgo) -> Result<Option<&'static str>> {
+        if let Some(source) = &self.source {
+            let source = Path::new(source);
+            if fs.symlink_metadata(source).is_err() {
+                return Ok(Some("源文件已不存在"));
+            }
+            if self.source_size.is_some() && path_size(fs, source).ok() != self.source_size {
+                return Ok(Some("源文件大小变化"));
+            }
+            if let Some(source_hash) = &self.source_hash {
+                if &to_hex(&hash_file(fs, source, algo)?) != source_hash {
+                    return Ok(Some("源文件内容变化"));
+                }
+            }
+        }
+        let destination = Path::new(&self.destination);
+        if path_size(fs, destination).ok() != self.destination_size {
+            return Ok(Some("目标文件已变化"));
+        }
+        if let Some(destination_hash) = &self.destination_hash {
+            if &to_hex(&hash_file(fs, destination, algo)?) != destination_hash {
+                return Ok(Some("目标文件内容变化"));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Display for DecisionResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.summary())
+    }
+}
+
+/// 文件属性，[`FileSystem`] 读取到的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub len: u64,
+    pub modified: SystemTime,
+    /// 最后访问时间，平台不支持时为 None
+    pub accessed: Option<SystemTime>,
+    /// 创建时间，文件系统不支持时用 ctime 等代替
+    pub created: SystemTime,
+    pub is_dir: bool,
+    pub is_file: bool,
+    /// 只有 symlink_metadata 的结果可能为 true
+    pub is_symlink: bool,
+    /// 权限位（仅 Unix，其他平台为 0）
+    pub mode: u32,
+}
+
+/// 扫描、分析、执行时用到的文件系统操作，默认为 [`RealFileSystem`]，
+/// 可通过 [`SyncContext::with_file_system`] 换成内存中的实现，不创建目录也能测试整个同步流程。
+/// 回收站、内容转换、写入 zip 等附加功能仍直接读写磁盘
+pub trait FileSystem: std::fmt::Debug + Send + Sync {
+    /// 解析符号链接后的绝对路径
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+    /// 文件属性，跟随符号链接
+    fn metadata(&self, path: &Path) -> io::Result<FileStat>;
+    /// 文件属性，符号链接取链接本身的
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FileStat>;
+    /// 符号链接指向的目标
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    /// 目录下各项的路径
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    /// 打开文件读取内容
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+    /// 打开文件写入，append 时追加到末尾，否则新建或清空
+    fn create(&self, path: &Path, append: bool) -> io::Result<Box<dyn Write + '_>>;
+    /// 把文件的内容同步到磁盘
+    fn sync(&self, path: &Path) -> io::Result<()>;
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+    /// 创建指向 target 的符号链接
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()>;
+    /// 复制文件内容，返回复制的字节数
+    fn copy(&self, src: &Path, dst: &Path) -> io::Result<u64>;
+    /// 把源文件的访问时间、修改时间（Windows 上还有创建时间）设置到目标文件
+    fn copy_times(&self, src: &Path, dst: &Path) -> io::Result<()>;
+    /// 把源文件的权限（Windows 上为只读属性）设置到目标文件
+    fn copy_permissions(&self, src: &Path, dst: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// 删除文件，或整个目录
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    /// 路径所在卷的剩余空间（字节）
+    fn available_space(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// 直接操作磁盘的 [`FileSystem`]
+#[derive(Debug, Default)]
+pub struct RealFileSystem;
+
+impl RealFileSystem {
+    fn stat(metadata: fs::Metadata) -> io::Result<FileStat> {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o7777
+        };
+        #[cfg(not(unix))]
+        let mode = 0;
+        Ok(FileStat {
+            len: metadata.len(),
+            modified: metadata.modified()?,
+            accessed: metadata.accessed().ok(),
+            created: created_time(&metadata),
+            is_dir: metadata.is_dir(),
+            is_file: metadata.is_file(),
+            is_symlink: metadata.is_symlink(),
+            mode,
+        })
+    }
+}
+
+impl FileSystem for RealFileSystem {
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileStat> {
+        RealFileSystem::stat(fs::metadata(path)?)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<FileStat> {
+        RealFileSystem::stat(fs::symlink_metadata(path)?)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?.map(|entry| Ok(entry?.path())).collect()
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &Path, append: bool) -> io::Result<Box<dyn Write + '_>> {
+        Ok(Box::new(if append { OpenOptions::new().append(true).open(path)? } else { File::create(path)? }))
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        OpenOptions::new().write(true).open(path)?.sync_all()
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        create_symlink(target, link)
+    }
+
+    fn copy(&self, src: &Path, dst: &Path) -> io::Result<u64> {
+        fs::copy(src, dst)
+    }
+
+    fn copy_times(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        copy_time(src, dst)
+    }
+
+    fn copy_permissions(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        fs::set_permissions(dst, fs::metadata(src)?.permissions())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            force_remove_file(path)
+        }
+    }
+
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        fs2::available_space(path)
+    }
+}
+
+#[derive(Debug)]
+pub struct DecisionTask {
+    from_dict_info: Arc<DirectoryInfo>,
+    to_dict_info: Arc<DirectoryInfo>,
+    context: Arc<SyncContext>,
+
+    _decision_result: DecisionResult,
+    _from_file_names: HashMap<String, Arc<FileInfo>>,
+    _to_file_names: HashMap<String, Arc<FileInfo>>,
+    _from_dict_names: HashMap<String, Arc<DirectoryInfo>>,
+    _to_dict_names: HashMap<String, Arc<DirectoryInfo>>,
+    /// 只需改正大小写的文件名（包括源文件名和目标文件名），不再参与新增、删除判断
+    _case_renamed_names: HashSet<String>,
+    /// 流水线模式下，每层目录分析完就把结果发给执行方
+    _pipeline: Option<mpsc::Sender<DecisionResult>>,
+}
+
+impl DecisionTask {
+    pub fn new(from_dict_info: Arc<DirectoryInfo>, to_dict_info: Arc<DirectoryInfo>, context: Arc<SyncContext>) -> Self {
+        Self {
+            from_dict_info: from_dict_info.clone(),
+            to_dict_info: to_dict_info.clone(),
+            _decision_result: DecisionResult::for_context(&context),
+            _from_file_names: from_dict_info.files.iter()
+                .map(|x| (context.name_key(&x.name), x.clone()))
+                .collect(),
+            _to_file_names: to_dict_info.files.iter()
+                .map(|x| (context.name_key(&x.name), x.clone()))
+                .collect(),
+            _from_dict_names: from_dict_info.sub_dirs.iter()
+                .map(|x| (context.name_key(&x.name()), x.clone()))
+                .collect(),
+            _to_dict_names: to_dict_info.sub_dirs.iter()
+                .map(|x| (context.name_key(&x.name()), x.clone()))
+                .collect(),
+            _case_renamed_names: HashSet::new(),
+            _pipeline: None,
+            context,
+        }
+    }
+
+    pub fn with_pipeline(mut self, sender: mpsc::Sender<DecisionResult>) -> Self {
+        self._pipeline = Some(sender);
+        self
+    }
+
+    /// 分析整个目录树。启用 detect_renames 时（流水线模式除外）再把内容相同的一对新增、删除换成移动
+    pub fn make_decision(self) -> Result<DecisionResult> {
+        let context = self.context.clone();
+        let pipelined = self._pipeline.is_some();
+        let mut decision_result = self.make_tree_decision()?;
+        if context.detect_renames && !pipelined {
+            decision_result.detect_renames(context.fs.as_ref(), context.hash_algo)?;
+        }
+        Ok(decision_result)
+    }
+
+    /// 分析本层目录，再递归分析两侧都有的子目录
+    fn make_tree_decision(mut self) -> Result<DecisionResult> {
+        let bidirectional = self.context.mode == SyncMode::BIDIRECTIONAL;
+        if self.context.fix_case && !bidirectional {
+            let rename_items = self.find_case_renames()?;
+            for it in rename_items.iter() {
+                self._case_renamed_names.insert(it.src_file_info.as_ref().unwrap().name.clone());
+                self._case_renamed_names.insert(it.dest_file_info.name.clone());
+            }
+            self._decision_result.rename_items
+                .insert(self.from_dict_info.relative_path(), rename_items);
+        }
+
+        let add_items = self.find_add()?;
+        self.insert_items(add_items);
+        // 双向同步时目标目录独有的内容复制回源目录（启用 state_db 时可能是源目录删除了）
+        let del_items = if bidirectional { self.find_reverse_add()? } else { self.find_del()? };
+        self.insert_items(del_items);
+        let update_items = self.find_update()?;
+        self.insert_items(update_items);
+
+        if let Some(sender) = &self._pipeline {
+            let batch = std::mem::replace(&mut self._decision_result, DecisionResult::for_context(&self.context));
+            // 执行方已中止时不再需要结果
+            let _ = sender.send(batch);
+        }
+
+        for (sub_src, sub_dest) in self.find_both_sub_dirs() {
+            let mut sub_task = DecisionTask::new(
+                sub_src.clone(),
+                sub_dest.clone(),
+                self.context.clone(),
+            );
+            sub_task._pipeline = self._pipeline.clone();
+            self._decision_result.merge(sub_task.make_tree_decision()?);
+        }
+
+        Ok(self._decision_result)
+    }
+
+    /// 按操作类型放入本层目录的分析结果
+    fn insert_items(&mut self, items: Vec<DecisionResultItem>) {
+        let result = &mut self._decision_result;
+        for it in items {
+            let items = match it.action {
+                FileAction::ADD => &mut result.add_items,
+                FileAction::DEL => &mut result.del_items,
+                FileAction::UPDATE => &mut result.update_items,
+                FileAction::KEEP => &mut result.keep_items,
+                FileAction::RENAME => &mut result.rename_items,
+                FileAction::CHMOD => &mut result.permission_items,
+                FileAction::SKIP => &mut result.skipped_items,
+                FileAction::TOUCH => &mut result.touch_items,
+                FileAction::CONFLICT => &mut result.conflict_items,
+            };
+            items.entry(self.from_dict_info.relative_path()).or_default().push(it);
+        }
+    }
+
+    /// 本层目录的子目录是否按递归处理：开启 recursive 且没有到达 max_depth
+    fn recursive(&self) -> bool {
+        let depth = Path::new(&self.from_dict_info.relative_path()).components().count();
+        self.context.recursive && self.context.within_max_depth(depth)
+    }
+
+    fn find_both_sub_dirs(&self) -> Vec<(Arc<DirectoryInfo>, Arc<DirectoryInfo>)> {
+        self.from_dict_info.sub_dirs.iter()
+            .filter(|it| self._to_dict_names.contains_key(&self.context.name_key(&it.name())))
+            .map(|it| (it.clone(), self._to_dict_names.get(&self.context.name_key(&it.name())).unwrap().clone()))
+            .collect()
+    }
+
+    /// 只根据文件名/目录名判断，源目录下有，新目录下没有，就新增。
+    /// 双向同步时交给 find_one_sided，启用 state_db 时可能是目标目录删除了
+    fn find_add(&self) -> Result<Vec<DecisionResultItem>> {
+        let mut add_items = Vec::new();
+        let bidirectional = self.context.mode == SyncMode::BIDIRECTIONAL;
+        // 判断目录
+        if self.recursive() {
+            for it in self.from_dict_info.sub_dirs.iter() {
+                if !self._to_dict_names.contains_key(&self.context.name_key(&it.name())) {
+                    if bidirectional {
+                        self.find_one_sided(Arc::new(it.to_file_info()), Some(it), true, &mut add_items)?;
+                    } else {
+                        add_items.push(self.add_item(Arc::new(it.to_file_info()), Some(it)));
+                    }
+                }
+            }
+        } else if self.context.include_empty_dirs {
+            // 不递归时子目录没有扫描，直接查看是否为空
+            for it in self.from_dict_info.sub_dirs.iter() {
+                if !self._to_dict_names.contains_key(&self.context.name_key(&it.name()))
+                    && self.context.fs.read_dir(Path::new(&it.absolute_dir))?.is_empty() {
+                    add_items.push(self.add_item(Arc::new(it.to_file_info()), Some(it)));
+                }
+            }
+        }
+        // 判断文件
+        for it in self.from_dict_info.files.iter() {
+            if !self._to_file_names.contains_key(&self.context.name_key(&it.name)) && !self._case_renamed_names.contains(&it.name) {
+                if bidirectional {
+                    self.find_one_sided(it.clone(), None, true, &mut add_items)?;
+                } else {
+                    add_items.push(self.add_item(it.clone(), None));
+                }
+            }
+        }
+        Ok(add_items)
+    }
+
+    /// 把源文件/目录（dir 为其扫描结果）新增到目标目录的条目，目标路径过长时为跳过
+    fn add_item(&self, src: Arc<FileInfo>, dir: Option<&DirectoryInfo>) -> DecisionResultItem {
+        let dest_file_info = self.gene_add_dest_file_info(&src);
+        let dest_len = path_length(&dest_file_info.absolute_dir_with_self())
+            + dir.map_or(0, |dir| dir.longest_descendant_len());
+        DecisionResultItem {
+            action: self.add_or_skip(dest_len),
+            src_file_info: Some(src),
+            dest_file_info: Arc::new(dest_file_info),
+            reason: None,
+        }
+    }
+
+    /// 目标路径（目录取其中最长的路径）超过 max_path_length 时跳过
+    fn add_or_skip(&self, dest_len: usize) -> FileAction {
+        match self.context.max_path_length {
+            Some(max_path_length) if dest_len > max_path_length => FileAction::SKIP,
+            _ => FileAction::ADD,
+        }
+    }
+
+    /// 双向同步：目标目录下有、源目录下没有的文件/目录，反向新增到源目录，
+    /// 启用 state_db 时可能是源目录删除了
+    fn find_reverse_add(&self) -> Result<Vec<DecisionResultItem>> {
+        let mut items = Vec::new();
+        if self.recursive() {
+            for it in self.to_dict_info.sub_dirs.iter() {
+                if !self._from_dict_names.contains_key(&self.context.name_key(&it.name())) {
+                    self.find_one_sided(Arc::new(it.to_file_info()), Some(it), false, &mut items)?;
+                }
+            }
+        }
+        for it in self.to_dict_info.files.iter() {
+            if !self._from_file_names.contains_key(&self.context.name_key(&it.name)) {
+                self.find_one_sided(it.clone(), None, false, &mut items)?;
+            }
+        }
+        Ok(items)
+    }
+
+    /// 双向同步时只在一侧的文件/目录（from_side 为 true 时在源目录，dir 为目录的扫描结果）：
+    /// 上次同步时不存在（或未启用 state_db）的复制到另一侧；上次同步后没有修改过的说明另一侧删除了，
+    /// 同样删除；修改过的为冲突。目录中有不能一起删除的内容时逐项处理
+    fn find_one_sided(&self, info: Arc<FileInfo>, dir: Option<&DirectoryInfo>, from_side: bool,
+                      items: &mut Vec<DecisionResultItem>) -> Result<()> {
+        match self.synced_change(&info)? {
+            None | Some(SyncedChange::New) => items.push(if from_side {
+                self.add_item(info, dir)
+            } else {
+                self.reverse_add_item(info)
+            }),
+            Some(SyncedChange::Changed) => items.push(DecisionResultItem {
+                action: FileAction::CONFLICT,
+                src_file_info: None,
+                dest_file_info: info,
+                reason: None,
+            }),
+            Some(SyncedChange::Unchanged) => match dir {
+                Some(dir) if self.has_kept_descendant(dir) || self.has_undeletable_descendant(dir)? => {
+                    for it in dir.sub_dirs.iter() {
+                        self.find_one_sided(Arc::new(it.to_file_info()), Some(it), from_side, items)?;
+                    }
+                    for it in dir.files.iter() {
+                        self.find_one_sided(it.clone(), None, from_side, items)?;
+                    }
+                }
+                _ => items.push(DecisionResultItem {
+                    action: if self.is_kept(&info.relative_path()) || !self.context.delete {
+                        FileAction::KEEP
+                    } else {
+                        FileAction::DEL
+                    },
+                    src_file_info: None,
+                    dest_file_info: info,
+                    reason: None,
+                }),
+            },
+        }
+        Ok(())
+    }
+
+    /// 反向操作的条目：src_file_info 为目标目录中的文件，dest_file_info 为源目录中对应的位置
+    fn reverse_add_item(&self, dest: Arc<FileInfo>) -> DecisionResultItem {
+        let mut absolute_path = PathBuf::from(&self.from_dict_info.root);
+        absolute_path.push(Path::new(&dest.relative_path_without_file()));
+        let src_file_info = FileInfo::new(
+            dest.name.clone(),
+            self.from_dict_info.root.clone(),
+            absolute_path.to_str().unwrap().to_string(),
+        );
+        DecisionResultItem {
+            action: FileAction::ADD,
+            src_file_info: Some(dest),
+            dest_file_info: Arc::new(src_file_info),
+            reason: Some(UpdateReason::OnlyInDestination),
+        }
+    }
+
+    /// 只根据文件名/目录名判断，源目录下没有，新目录下有，就删除。
+    /// 命中 keep 的条目不删除，以保留操作返回
+    fn find_del(&self) -> Result<Vec<DecisionResultItem>> {
+        let mut items = Vec::new();
+        // --since 过滤掉的源文件不代表已删除
+        if !self.context.delete || self.context.since.is_some() {
+            return Ok(items);
+        }
+        // 判断目录
+        if self.recursive() {
+            for it in self.to_dict_info.sub_dirs.iter() {
+                if !self._from_dict_names.contains_key(&self.context.name_key(&it.name())) {
+                    self.find_del_in_dir(it, &mut items)?;
+                }
+            }
+        }
+        // 判断文件
+        for it in self.to_dict_info.files.iter() {
+            if !self._from_file_names.contains_key(&self.context.name_key(&it.name)) && !self._case_renamed_names.contains(&it.name) {
+                items.push(self.del_or_keep_item(it.clone())?);
+            }
+        }
+        Ok(items)
+    }
+
+    /// 目标目录独有的目录：本身需要保留则整体保留，其下没有需要保留的内容则整体删除，
+    /// 否则逐项处理，保证需要保留的文件不会随目录一起被删除
+    fn find_del_in_dir(&self, dir: &DirectoryInfo, items: &mut Vec<DecisionResultItem>) -> Result<()> {
+        let item = self.del_or_keep_item(Arc::new(dir.to_file_info()))?;
+        if matches!(item.action, FileAction::DEL)
+            && (self.has_kept_descendant(dir) || self.has_undeletable_descendant(dir)?) {
+            for it in dir.sub_dirs.iter() {
+                self.find_del_in_dir(it, items)?;
+            }
+            for it in dir.files.iter() {
+                items.push(self.del_or_keep_item(it.clone())?);
+            }
+        } else {
+            items.push(item);
+        }
+        Ok(())
+    }
+
+    fn has_kept_descendant(&self, dir: &DirectoryInfo) -> bool {
+        dir.files.iter().any(|it| self.is_kept(&it.relative_path()))
+            || dir.sub_dirs.iter().any(|it| self.is_kept(&it.relative_path()) || self.has_kept_descendant(it))
+    }
+
+    /// 启用 state_db 时，目录下是否有上次同步后新增或修改过、不能随目录一起删除的内容
+    fn has_undeletable_descendant(&self, dir: &DirectoryInfo) -> Result<bool> {
+        for it in dir.files.iter() {
+            if matches!(self.synced_change(it)?, Some(SyncedChange::New | SyncedChange::Changed)) {
+                return Ok(true);
+            }
+        }
+        for it in dir.sub_dirs.iter() {
+            if matches!(self.synced_change(&it.to_file_info())?, Some(SyncedChange::New))
+                || self.has_undeletable_descendant(it)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn is_kept(&self, relative_path: &str) -> bool {
+        self.context.keep.is_match(relative_path)
+    }
+
+    /// 源目录中没有的目标文件/目录：命中 keep 的保留；启用 state_db 时，上次同步时不存在的是目标目录新增的，
+    /// 同样保留，上次同步后修改过的为冲突，其余说明源目录删除了，同步删除
+    fn del_or_keep_item(&self, dest_file_info: Arc<FileInfo>) -> Result<DecisionResultItem> {
+        let action = if self.is_kept(&dest_file_info.relative_path()) {
+            FileAction::KEEP
+        } else {
+            match self.synced_change(&dest_file_info)? {
+                None | Some(SyncedChange::Unchanged) => FileAction::DEL,
+                Some(SyncedChange::New) => FileAction::KEEP,
+                Some(SyncedChange::Changed) => FileAction::CONFLICT,
+            }
+        };
+        Ok(DecisionResultItem {
+            action,
+            src_file_info: None,
+            dest_file_info,
+            reason: None,
+        })
+    }
+
+    /// 与 state_db 中上次同步时的状态相比，文件/目录的变化；未启用 state_db 或没有状态记录时为 None。
+    /// 目录只看上次同步时其下是否有文件，其中的内容由调用方逐项判断
+    fn synced_change(&self, info: &FileInfo) -> Result<Option<SyncedChange>> {
+        let Some(synced_files) = &self.context.synced_files else {
+            return Ok(None);
+        };
+        let relative_path = info.relative_path();
+        if self.stat(info)?.is_dir && info.link_target.is_none() {
+            let existed = synced_files.keys().any(|path| Path::new(path).starts_with(&relative_path));
+            return Ok(Some(if existed { SyncedChange::Unchanged } else { SyncedChange::New }));
+        }
+        Ok(Some(match synced_files.get(&relative_path) {
+            None => SyncedChange::New,
+            Some(synced) if synced.is_changed(self.context.fs.as_ref(), info, self.context.hash_algo)? => SyncedChange::Changed,
+            Some(_) => SyncedChange::Unchanged,
+        }))
+    }
+
+    /// 找出文件名只有大小写不同、内容相同的文件，把目标文件改名即可，无需删除再复制
+    fn find_case_renames(&self) -> Result<Vec<DecisionResultItem>> {
+        // case_insensitive 时映射的键不区分大小写，这里按原始文件名判断
+        let from_names: HashSet<&str> = self.from_dict_info.files.iter().map(|it| it.name.as_str()).collect();
+        let to_names: HashSet<&str> = self.to_dict_info.files.iter().map(|it| it.name.as_str()).collect();
+        let to_lowercase_names: HashMap<String, &Arc<FileInfo>> = self.to_dict_info.files.iter()
+            .filter(|it| !from_names.contains(it.name.as_str()))
+            .map(|it| (it.name.to_lowercase(), it))
+            .collect();
+        let mut items = Vec::new();
+        for it in self.from_dict_info.files.iter() {
+            if to_names.contains(it.name.as_str()) || it.link_target.is_some() {
+                continue;
+            }
+            if let Some(&dest) = to_lowercase_names.get(&it.name.to_lowercase()) {
+                if is_same_file(self.context.fs.as_ref(), &it.to_path(), &dest.to_path())? {
+                    items.push(DecisionResultItem {
+                        action: FileAction::RENAME,
+                        src_file_info: Some(it.clone()),
+                        dest_file_info: dest.clone(),
+                        reason: None,
+                    });
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    /// 根据配置判断更新了的文件。force_copy 指定的文件直接更新，其余按 [`DecisionTask::check_has_updated`] 的顺序比较：
+    /// 符号链接只比较指向的目标；大小、修改时间都相同时跳过（deep_compare、compare: content 时仍比较内容）；
+    /// content_filter 匹配的文件比较转换后的内容；append_only 时目标是源的前缀则只追加；
+    /// 然后大小不同即更新，修改时间不同即更新（deep_compare、--prime、compare: content 或不保留修改时间时不看），
+    /// 最后按 compare 逐字节或按哈希比较内容。
+    /// 因为新增、删除在其他任务里了，这里只需要管两边都有的文件即可
+    /// 返回需要更新的文件，以及内容无需更新但权限不同（仅 Unix）、需要对齐时间（--prime）的文件
+    fn find_update(&self) -> Result<Vec<DecisionResultItem>> {
+        let mut items = Vec::new();
+        for it in self.to_dict_info.files.iter() {
+            // 改正大小写的文件已由改名处理
+            if self._case_renamed_names.contains(&it.name) {
+                continue;
+            }
+            let Some(src_file_info) = self._from_file_names.get(&self.context.name_key(&it.name)).cloned() else {
+                continue;
+            };
+            let forced = self.context.force_copy.iter()
+                .any(|path| path == Path::new(&it.relative_path()));
+            let reason = if forced {
+                Some(UpdateReason::Forced)
+            } else {
+                self.check_has_updated(&src_file_info, it)?
+            };
+            if let (Some(reason), SyncMode::BIDIRECTIONAL) = (reason, self.context.mode) {
+                items.push(self.newest_wins_item(src_file_info.clone(), it.clone(), reason)?);
+            } else if let Some(reason) = reason {
+                items.push(DecisionResultItem {
+                    action: FileAction::UPDATE,
+                    src_file_info: Some(src_file_info.clone()),
+                    dest_file_info: it.clone(),
+                    reason: Some(reason),
+                });
+            } else {
+                if self.has_different_permissions(&src_file_info, it)? {
+                    items.push(DecisionResultItem {
+                        action: FileAction::CHMOD,
+                        src_file_info: Some(src_file_info.clone()),
+                        dest_file_info: it.clone(),
+                        reason: Some(UpdateReason::PermissionDiffers),
+                    });
+                }
+                // 内容相同只是修改时间不同，对齐时间后下次运行无需再比较内容
+                if self.context.prime && self.context.preserve_times && src_file_info.link_target.is_none()
+                    && self.stat(&src_file_info)?.modified != self.stat(it)?.modified {
+                    items.push(DecisionResultItem {
+                        action: FileAction::TOUCH,
+                        src_file_info: Some(src_file_info.clone()),
+                        dest_file_info: it.clone(),
+                        reason: None,
+                    });
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    /// 双向同步时两侧不同的文件：两侧都在上次同步后修改过为冲突，否则以修改时间较新的一侧为准，
+    /// 目标较新时反向复制回源目录。修改时间相同（如强制复制、deep_compare）时以源为准
+    fn newest_wins_item(&self, src: Arc<FileInfo>, dest: Arc<FileInfo>, reason: UpdateReason) -> Result<DecisionResultItem> {
+        let (src_modified, dest_modified) = (self.stat(&src)?.modified, self.stat(&dest)?.modified);
+        // 启用 state_db 时按上次同步时的状态判断哪一侧修改过，两侧都修改过（或都是新增）为冲突
+        let (src_change, dest_change) = (self.synced_change(&src)?, self.synced_change(&dest)?);
+        let dest_newer = match (src_change, dest_change) {
+            (Some(SyncedChange::Unchanged), Some(SyncedChange::Unchanged)) | (None, _) | (_, None) => {
+                dest_modified > src_modified
+            }
+            (Some(SyncedChange::Unchanged), Some(_)) => true,
+            (Some(_), Some(SyncedChange::Unchanged)) => false,
+            (Some(_), Some(_)) => return Ok(DecisionResultItem {
+                action: FileAction::CONFLICT,
+                src_file_info: Some(src),
+                dest_file_info: dest,
+                reason: None,
+            }),
+        };
+        if let (Some(last_synced), None) = (self.context.last_synced, src_change) {
+            if src_modified > last_synced && dest_modified > last_synced {
+                return Ok(DecisionResultItem {
+                    action: FileAction::CONFLICT,
+                    src_file_info: Some(src),
+                    dest_file_info: dest,
+                    reason: None,
+                });
+            }
+        }
+        Ok(if dest_newer && reason != UpdateReason::Forced {
+            DecisionResultItem {
+                action: FileAction::UPDATE,
+                src_file_info: Some(dest),
+                dest_file_info: src,
+                reason: Some(UpdateReason::NewerInDestination),
+            }
+        } else {
+            DecisionResultItem {
+                action: FileAction::UPDATE,
+                src_file_info: Some(src),
+                dest_file_info: dest,
+                reason: Some(reason),
+            }
+        })
+    }
+
+    /// 文件属性，保留的符号链接取链接本身的
+    fn stat(&self, info: &FileInfo) -> Result<FileStat> {
+        let path = info.to_path();
+        let stat = if info.link_target.is_some() {
+            self.context.fs.symlink_metadata(&path)
+        } else {
+            self.context.fs.metadata(&path)
+        };
+        stat.with_context(|| format!("无法读取文件属性: {}", info.absolute_dir_with_self()))
+    }
+
+    /// 源、目标文件的权限位是否不同，非 Unix 平台不比较
+    fn has_different_permissions(&self, src: &FileInfo, dest: &FileInfo) -> Result<bool> {
+        if !cfg!(unix) || src.link_target.is_some() || dest.link_target.is_some() {
+            return Ok(false);
+        }
+        Ok(self.stat(src)?.mode != self.stat(dest)?.mode)
+    }
+
+    /// 判断文件是否需要更新，需要时返回原因
+    fn check_has_updated(&self, src_info: &FileInfo, dest_info: &FileInfo) -> Result<Option<UpdateReason>> {
+        // 符号链接只比较指向的目标
+        if src_info.link_target.is_some() || dest_info.link_target.is_some() {
+            return Ok(if src_info.link_target == dest_info.link_target {
+                None
+            } else {
+                Some(UpdateReason::LinkTargetDiffers)
+            });
+        }
+        let (src_meta, dest_meta) = (self.stat(src_info)?, self.stat(dest_info)?);
+        // deep_compare 时修改时间相同也比较内容，compare: content 时不看修改时间
+        let content_only = self.context.compare == CompareMode::Content;
+        let same_mtime = src_meta.modified == dest_meta.modified;
+        let filter = self.context.content_filter.as_ref()
+            .filter(|filter| filter.glob.is_match(src_info.relative_path()));
+        // 大小不同时修改时间相同也要更新；转换内容的文件本就与源文件大小不同，不在这里比较
+        let same_size = src_meta.len == dest_meta.len || filter.is_some();
+        if same_mtime && same_size && !self.context.deep_compare && !content_only {
+            return Ok(None);
+        }
+        // 需要转换内容的文件，用转换后的结果与目标文件比较
+        if let Some(filter) = filter {
+            let transformed = filter.output(&src_info.to_path())?;
+            let mut dest_content = Vec::new();
+            dest_info.file(self.context.fs.as_ref())?.read_to_end(&mut dest_content)
+                .with_context(|| format!("无法读取文件: {}", dest_info.absolute_dir_with_self()))?;
+            return Ok(if transformed == dest_content {
+                None
+            } else if transformed.len() != dest_content.len() {
+                Some(UpdateReason::SizeDiffers)
+            } else {
+                Some(UpdateReason::ContentDiffers)
+            });
+        }
+        // 只追加的文件：比较较短一方长度的前缀，一致时目标不比源短就已包含源的内容，源更长则只追加多出的部分。
+        // 前缀不一致说明源文件被改写过，按普通文件比较
+        if self.context.append_only {
+            let fs = self.context.fs.as_ref();
+            let prefix_len = src_meta.len.min(dest_meta.len);
+            let dest_hash = hash_prefix(&mut dest_info.file(fs)?, prefix_len, self.context.hash_algo)?;
+            if dest_hash == hash_prefix(&mut src_info.file(fs)?, prefix_len, self.context.hash_algo)? {
+                return Ok((dest_meta.len < src_meta.len).then_some(UpdateReason::Appended));
+            }
+        }
+        if src_meta.len != dest_meta.len {
+            return Ok(Some(UpdateReason::SizeDiffers));
+        }
+        // --prime 要找出内容相同只需对齐时间的文件，总是比较内容
+        // 不对齐修改时间时，时间不同不能说明内容有变化
+        if !self.context.deep_compare && !self.context.prime && self.context.preserve_times && !content_only {
+            return Ok(Some(UpdateReason::MtimeDiffers));
+        }
+        Ok(match self.context.compare {
+            CompareMode::MtimeThenBytes => {
+                let same = is_same_file(self.context.fs.as_ref(), &src_info.to_path(), &dest_info.to_path())
+                    .with_context(|| format!("比较文件内容失败: {}", src_info.absolute_dir_with_self()))?;
+                (!same).then_some(UpdateReason::ContentDiffers)
+            }
+            CompareMode::Hash | CompareMode::Content => {
+                let fs = self.context.fs.as_ref();
+                let same = src_info.content_hash(fs, self.context.hash_algo)?
+                    == dest_info.content_hash(fs, self.context.hash_algo)?;
+                (!same).then_some(UpdateReason::HashDiffers)
+            }
+        })
+    }
+
+    fn gene_add_dest_file_info(&self, src: &FileInfo) -> FileInfo {
+        // 关键在于根据相对目录生成目标的绝对目录
+        let mut absolute_path = PathBuf::from(&self.to_dict_info.root);
+        absolute_path.push(Path::new(&src.relative_path_without_file()));
+        FileInfo::new(
+            src.name.clone(),
+            self.to_dict_info.root.clone(),
+            absolute_path.to_str().unwrap().to_string(),
+        )
+    }
+}
+
+/// retries：第一次重试前的等待时间
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+pub struct DecisionExecuteTask {
+    decision: DecisionResult,
+    context: Arc<SyncContext>,
+
+    _total_count: usize,
+    _processed_count: AtomicUsize,
+    _total_bytes: u64,
+    _processed_bytes: AtomicU64,
+    /// 本次运行删除的文件在回收站中的批次目录名
+    _trash_batch: String,
+    /// 目标剩余空间低于下限后置位，后续操作不再执行
+    _stopped: AtomicBool,
+    /// --report-csv 指定的执行报告，多组目录共用
+    _report: Option<Arc<CsvReport>>,
+    /// --dry-run：只打印每项操作，不实际执行
+    _dry_run: bool,
+    /// 不指定 --verbose 时用进度条代替逐行输出
+    _progress_bar: Option<ProgressBar>,
+    /// --quiet：不输出每项操作
+    _quiet: bool,
+    /// --keep-going：失败的操作记录到 _failures 后继续执行
+    _keep_going: bool,
+    _failures: Mutex<Vec<(PathBuf, anyhow::Error)>>,
+    /// 成功复制、更新的文件数和字节数，用于结束时显示平均速度
+    _copied_files: AtomicUsize,
+    _copied_bytes: AtomicU64,
+    /// log_file 配置的审计日志
+    _audit_log: Option<AuditLog>,
+    /// --interactive：删除、更新前是否还需要逐项确认，回答 a 后置为 false
+    _confirm_del: AtomicBool,
+    _confirm_update: AtomicBool,
+    /// 多线程执行时同一时间只询问一项
+    _prompt_lock: Mutex<()>,
+    /// 逐项确认时回答了 q
+    _quit: AtomicBool,
+}
+
+impl DecisionExecuteTask {
+    pub fn new(decision: DecisionResult, context: Arc<SyncContext>) -> Self {
+        let total_count = decision.total_count();
+        // 按字节显示进度时才需要统计，避免多余的文件读取
+        let total_bytes = if context.progress_by_bytes { decision.total_bytes() } else { 0 };
+        Self {
+            decision,
+            context,
+            _total_count: total_count,
+            _processed_count: AtomicUsize::new(0),
+            _total_bytes: total_bytes,
+            _processed_bytes: AtomicU64::new(0),
+            _trash_batch: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs().to_string(),
+            _stopped: AtomicBool::new(false),
+            _report: None,
+            _dry_run: false,
+            _progress_bar: None,
+            _quiet: false,
+            _keep_going: false,
+            _failures: Mutex::new(Vec::new()),
+            _copied_files: AtomicUsize::new(0),
+            _copied_bytes: AtomicU64::new(0),
+            _audit_log: None,
+            _confirm_del: AtomicBool::new(false),
+            _confirm_update: AtomicBool::new(false),
+            _prompt_lock: Mutex::new(()),
+            _quit: AtomicBool::new(false),
+        }
+    }
+
+    /// 显示进度条，当前项显示在进度条后面，不再逐行输出；输出不是终端时不显示
+    pub fn with_progress_bar(mut self) -> Self {
+        let (length, template) = if self.context.progress_by_bytes {
+            (self._total_bytes, "[{bar:40}] {bytes}/{total_bytes} 剩余 {eta} {wide_msg}")
+        } else {
+            (self._total_count as u64, "[{bar:40}] {pos}/{len} 剩余 {eta} {wide_msg}")
+        };
+        let bar = ProgressBar::new(length);
+        bar.set_style(ProgressStyle::with_template(template).unwrap().progress_chars("=> "));
+        self._progress_bar = Some(bar);
+        self
+    }
+
+    /// 有进度条时输出在进度条上方，避免打乱进度条
+    fn print_line(&self, line: &str) {
+        match &self._progress_bar {
+            Some(bar) => bar.println(line),
+            None => println!("{}", line),
+        }
+    }
+
+    /// 按 retries 配置重试失败的操作（如网络盘的短暂不可用），每次等待的时间翻倍，
+    /// 最后一次仍失败时返回其错误。op 的参数表示是否为重试
+    fn with_retries(&self, item: &DecisionResultItem, mut op: impl FnMut(bool) -> Result<()>) -> Result<()> {
+        let mut delay = RETRY_BASE_DELAY;
+        for attempt in 1..=self.context.retries {
+            match op(attempt > 1) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    self.print_line(&format!("{:?} 失败: {}: {:#}，{} 秒后第 {}/{} 次重试",
+                                             item.action,
+                                             adjust_canonicalization(item.dest_file_info.absolute_dir_with_self()),
+                                             e, delay.as_secs_f64(), attempt, self.context.retries));
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+            }
+        }
+        op(self.context.retries > 0)
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self._dry_run = dry_run;
+        self
+    }
+
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self._quiet = quiet;
+        self
+    }
+
+    pub fn with_keep_going(mut self, keep_going: bool) -> Self {
+        self._keep_going = keep_going;
+        self
+    }
+
+    /// 每完成一项操作，往 CSV 报告写入一行
+    pub(crate) fn with_csv_report(mut self, report: Arc<CsvReport>) -> Self {
+        self._report = Some(report);
+        self
+    }
+
+    /// 执行每项删除（updates 时还有每项更新）前逐项确认
+    pub fn with_interactive(mut self, updates: bool) -> Self {
+        self._confirm_del = AtomicBool::new(true);
+        self._confirm_update = AtomicBool::new(updates);
+        self
+    }
+
+    /// --interactive 时询问是否执行该项，回答 a 后同类操作不再询问，回答 q 时停止所有后续操作
+    fn confirm(&self, item: &DecisionResultItem) -> Result<bool> {
+        let pending = match item.action {
+            FileAction::DEL => &self._confirm_del,
+            FileAction::UPDATE => &self._confirm_update,
+            _ => return Ok(true),
+        };
+        if self._dry_run {
+            return Ok(true);
+        }
+        let _guard = self._prompt_lock.lock().unwrap();
+        if !pending.load(Ordering::Relaxed) {
+            return Ok(true);
+        }
+        if self._stopped.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let path = adjust_canonicalization(item.dest_file_info.absolute_dir_with_self());
+        Ok(match ask_item(&format!("{:?} {}？", item.action, path))? {
+            ItemAnswer::Yes => true,
+            ItemAnswer::No => false,
+            ItemAnswer::All => {
+                pending.store(false, Ordering::Relaxed);
+                true
+            }
+            ItemAnswer::Quit => {
+                self._quit.store(true, Ordering::Relaxed);
+                self._stopped.store(true, Ordering::Relaxed);
+                false
+            }
+        })
+    }
+
+    /// 每成功完成一项新增、删除、更新，往审计日志追加一行
+    pub(crate) fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self._audit_log = Some(audit_log);
+        self
+    }
+
+    /// 操作前目标文件的大小和修改时间，没有审计日志或目标不存在时为 None
+    fn dest_stat_before(&self, item: &DecisionResultItem) -> Option<(u64, i64)> {
+        self._audit_log.as_ref()?;
+        file_stat(self.context.fs.as_ref(), &item.dest_file_info.to_path())
+    }
+
+    fn audit(&self, item: &DecisionResultItem, dest_before: Option<(u64, i64)>, result: &Result<()>) -> Result<()> {
+        match &self._audit_log {
+            Some(audit_log) if result.is_ok() => audit_log.write_entry(self.context.fs.as_ref(), item, dest_before),
+            _ => Ok(()),
+        }
+    }
+
+    fn report(&self, item: &DecisionResultItem, started: Instant, result: &Result<()>) -> Result<()> {
+        if let Some(report) = &self._report {
+            report.write_row(self.context.fs.as_ref(), item, started.elapsed(), result)?;
+        }
+        Ok(())
+    }
+
+    /// 记录一项操作的结果，失败时在错误中注明操作和路径；--keep-going 时只记下失败项
+    fn finish(&self, item: &DecisionResultItem, started: Instant, result: Result<()>) -> Result<()> {
+        self.report(item, started, &result)?;
+        if result.is_ok() && matches!(item.action, FileAction::ADD | FileAction::UPDATE) {
+            let (bytes, files) = path_size_and_count(self.context.fs.as_ref(), &item.dest_file_info.to_path())
+                .unwrap_or((0, 0));
+            self._copied_files.fetch_add(files, Ordering::Relaxed);
+            self._copied_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+        let path = adjust_canonicalization(item.dest_file_info.absolute_dir_with_self());
+        match result {
+            Err(e) if self._keep_going => {
+                let e = e.context(format!("{:?} 失败", item.action));
+                self.print_line(&format!("{}: {:#}，继续执行", path, e));
+                self._failures.lock().unwrap().push((PathBuf::from(path), e));
+                Ok(())
+            }
+            result => result.with_context(|| format!("{:?} 失败: {}", item.action, path)),
+        }
+    }
+
+    /// 列出 --keep-going 记下的失败项，有失败时返回错误
+    fn check_failures(&self) -> Result<()> {
+        let failures = self._failures.lock().unwrap();
+        if failures.is_empty() {
+            return Ok(());
+        }
+        println!("以下 {} 项操作失败：", failures.len());
+        for (path, e) in failures.iter() {
+            println!("    {}: {:#}", path.display(), e);
+        }
+        Err(OperationsFailed(failures.len()).into())
+    }
+
+    /// 返回是否全部执行完毕（没有因空间不足中止），遇到失败的操作时停止并返回错误
+    pub fn execute(self) -> Result<bool> {
+        println!("同步任务开始执行");
+        let started = Instant::now();
+        self.execute_tasks()?;
+        self.print_finished(started);
+        self.check_failures()?;
+        Ok(!self._stopped.load(Ordering::Relaxed))
+    }
+
+    /// 流水线模式：逐批执行分析方送来的结果，总数随批次累加
+    pub fn execute_pipeline(mut self, batches: mpsc::Receiver<DecisionResult>) -> Result<bool> {
+        println!("同步任务开始执行");
+        let started = Instant::now();
+        for batch in batches {
+            self._total_count += batch.total_count();
+            if self.context.progress_by_bytes {
+                self._total_bytes += batch.total_bytes();
+            }
+            if let Some(bar) = &self._progress_bar {
+                bar.set_length(if self.context.progress_by_bytes {
+                    self._total_bytes
+                } else {
+                    self._total_count as u64
+                });
+            }
+            self.decision = batch;
+            self.execute_tasks()?;
+            if self._stopped.load(Ordering::Relaxed) {
+                break;
+            }
+        }
+        self.print_finished(started);
+        self.check_failures()?;
+        Ok(!self._stopped.load(Ordering::Relaxed))
+    }
+
+    fn execute_tasks(&self) -> Result<()> {
+        self.execute_rename_task()?;
+        self.execute_add_task()?;
+        self.execute_update_task()?;
+        self.execute_permission_task()?;
+        self.execute_touch_task()?;
+        self.execute_del_task()
+    }
+
+    fn print_finished(&self, started: Instant) {
+        if let Some(bar) = &self._progress_bar {
+            bar.finish_and_clear();
+        }
+        if self._dry_run {
+            println!("DRY RUN — 未改动任何文件");
+            return;
+        } else if self._quit.load(Ordering::Relaxed) {
+            println!("同步任务已按要求停止，其余操作未执行");
+        } else if self._stopped.load(Ordering::Relaxed) {
+            println!("同步任务已中止，未完成的操作可在释放空间后重新运行");
+        } else {
+            println!("同步任务执行完毕");
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        let bytes = self._copied_bytes.load(Ordering::Relaxed);
+        println!("完成: {} 个文件，{}，用时 {:.1} 秒（{}/s）",
+                 self._copied_files.load(Ordering::Relaxed), format_bytes(bytes), elapsed,
+                 format_bytes((bytes as f64 / elapsed.max(0.001)) as u64));
+    }
+
+    /// 复制前检查目标剩余空间，低于 min_free_space 时停止后续所有操作
+    fn should_stop(&self) -> Result<bool> {
+        if self._stopped.load(Ordering::Relaxed) {
+            return Ok(true);
+        }
+        if let Some(min_free_space) = self.context.min_free_space {
+            let available = self.context.fs.available_space(Path::new(&self.context.to.path))
+                .with_context(|| format!("无法获取目标目录剩余空间: {}", self.context.to.path))?;
+            if available < min_free_space {
+                self.print_line(&format!("目标目录剩余空间 {} 字节，低于下限 {} 字节，停止执行",
+                                         available, min_free_space));
+                self._stopped.store(true, Ordering::Relaxed);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn log_progress(&self, counter: &AtomicUsize, item: &DecisionResultItem) {
+        if let Some(bar) = &self._progress_bar {
+            if matches!(item.action, FileAction::KEEP | FileAction::SKIP | FileAction::CONFLICT) {
+                return;
+            }
+            counter.fetch_add(1, Ordering::Relaxed);
+            bar.set_message(item.dest_file_info.relative_path());
+            bar.inc(if self.context.progress_by_bytes { self.item_size(item) } else { 1 });
+            return;
+        }
+        if self._quiet {
+            counter.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        match item.action {
+            FileAction::ADD => {
+                let prefix = self.progress_prefix(counter, item);
+                println!("{}  Copying - {} to {}", prefix,
+                         adjust_canonicalization(item.src_file_info.as_ref().unwrap()
+                             .absolute_dir_with_self()),
+                         adjust_canonicalization(item.dest_file_info.absolute_dir_with_self())
+                );
+            }
+            FileAction::DEL => {
+                let prefix = self.progress_prefix(counter, item);
+                println!("{}  Deleting - {}", prefix,
+                         adjust_canonicalization(item.dest_file_info.absolute_dir_with_self())
+                );
+            }
+            FileAction::UPDATE => {
+                let prefix = self.progress_prefix(counter, item);
+                println!("{}  Updating - {} to {}", prefix,
+                         adjust_canonicalization(item.src_file_info.as_ref().unwrap()
+                             .absolute_dir_with_self()),
+                         adjust_canonicalization(item.dest_file_info.absolute_dir_with_self())
+                );
+            }
+            FileAction::RENAME => {
+                let prefix = self.progress_prefix(counter, item);
+                println!("{}  Renaming - {} to {}", prefix,
+                         adjust_canonicalization(item.dest_file_info.absolute_dir_with_self()),
+                         adjust_canonicalization(item.rename_target().to_str().unwrap().to_string())
+                );
+            }
+            FileAction::CHMOD => {
+                let prefix = self.progress_prefix(counter, item);
+                if self.context.preserve_permissions {
+                    println!("{}  Chmod - {}", prefix,
+                             adjust_canonicalization(item.dest_file_info.absolute_dir_with_self())
+                    );
+                } else {
+                    println!("{}  Permission differs - {}（未开启 preserve_permissions，不修改）", prefix,
+                             adjust_canonicalization(item.dest_file_info.absolute_dir_with_self())
+                    );
+                }
+            }
+            FileAction::TOUCH => {
+                let prefix = self.progress_prefix(counter, item);
+                println!("{}  Touching - {}", prefix,
+                         adjust_canonicalization(item.dest_file_info.absolute_dir_with_self())
+                );
+            }
+            FileAction::KEEP | FileAction::SKIP | FileAction::CONFLICT => {}
+        }
+    }
+
+    fn progress_prefix(&self, counter: &AtomicUsize, item: &DecisionResultItem) -> String {
+        if self.context.progress_by_bytes {
+            self.bytes_progress_prefix(item)
+        } else {
+            self.count_and_progress_prefix(counter)
+        }
+    }
+
+    /// 按已处理字节数占总字节数的百分比显示进度，删除操作不计字节
+    fn bytes_progress_prefix(&self, item: &DecisionResultItem) -> String {
+        let size = self.item_size(item);
+        let processed = self._processed_bytes.fetch_add(size, Ordering::Relaxed) + size;
+        let percent = if self._total_bytes == 0 {
+            100.0
+        } else {
+            processed as f64 * 100.0 / self._total_bytes as f64
+        };
+        format!("{:>5.1}%", percent)
+    }
+
+    fn item_size(&self, item: &DecisionResultItem) -> u64 {
+        match &item.src_file_info {
+            Some(src) => path_size(self.context.fs.as_ref(), &src.to_path()).unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    fn count_and_progress_prefix(&self, counter: &AtomicUsize) -> String {
+        let cnt = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        format!("{}/{}", cnt, self._total_count)
+    }
+
+    fn execute_rename_task(&self) -> Result<()> {
+        for items in self.decision.rename_items.values() {
+            for it in items {
+                if self._stopped.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                self.log_progress(&self._processed_count, it);
+                if self._dry_run {
+                    continue;
+                }
+                let op_started = Instant::now();
+                // 移动后对齐修改时间（preserve_times），下次运行不会因时间不同再更新
+                let new_path = it.rename_target();
+                let fs = self.context.fs.as_ref();
+                let result = fs.rename(&it.dest_file_info.to_path(), &new_path)
+                    .and_then(|_| if self.context.preserve_times {
+                        fs.copy_times(&it.src_file_info.as_ref().unwrap().to_path(), &new_path)
+                    } else {
+                        Ok(())
+                    })
+                    .map_err(Into::into);
+                self.finish(it, op_started, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_add_task(&self) -> Result<()> {
+        self.for_each_parallel(self.decision.add_items.values().flatten().collect(), |it| {
+            if self.should_stop()? {
+                return Ok(());
+            }
+            self.log_progress(&self._processed_count, it);
+            if self._dry_run {
+                return Ok(());
+            }
+            let op_started = Instant::now();
+            let dest_before = self.dest_stat_before(it);
+            // 重试时覆盖上次失败留下的不完整文件
+            let result = self.with_retries(it, |retrying| copy_recursively(
+                Path::new(&it.src_file_info.as_ref().unwrap().absolute_dir_with_self()),
+                Path::new(&it.dest_file_info.absolute_dir_with_self()),
+                retrying,
+                &self.context,
+            ).and_then(|_| if self.context.verify_tree_counts {
+                verify_tree_count(
+                    Path::new(&it.src_file_info.as_ref().unwrap().absolute_dir_with_self()),
+                    Path::new(&it.dest_file_info.absolute_dir_with_self()),
+                )
+            } else {
+                Ok(())
+            }));
+            self.audit(it, dest_before, &result)?;
+            self.finish(it, op_started, result)
+        })
+    }
+
+    /// 新增、更新的各项互不依赖，按 threads 配置用多个线程同时处理，不大于 1 时逐项处理
+    fn for_each_parallel<'a>(&self, items: Vec<&'a DecisionResultItem>,
+                             f: impl Fn(&'a DecisionResultItem) -> Result<()> + Sync) -> Result<()> {
+        if self.context.threads <= 1 {
+            return items.into_iter().try_for_each(f);
+        }
+        let queue = Mutex::new(items.into_iter());
+        // 有一项失败后其余线程不再取新的项，最后返回第一个错误
+        let first_error = Mutex::new(None);
+        thread::scope(|scope| {
+            for _ in 0..self.context.threads {
+                scope.spawn(|| loop {
+                    if first_error.lock().unwrap().is_some() {
+                        break;
+                    }
+                    let next = queue.lock().unwrap().next();
+                    match next {
+                        Some(it) => if let Err(e) = f(it) {
+                            first_error.lock().unwrap().get_or_insert(e);
+                        },
+                        None => break,
+                    }
+                });
+            }
+        });
+        match first_error.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn execute_permission_task(&self) -> Result<()> {
+        // 未开启 preserve_permissions 时只在分析结果中报告
+        if !self.context.preserve_permissions {
+            return Ok(());
+        }
+        for items in self.decision.permission_items.values() {
+            for it in items {
+                if self._stopped.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                self.log_progress(&self._processed_count, it);
+                if self._dry_run {
+                    continue;
+                }
+                let op_started = Instant::now();
+                let result = self.context.fs.copy_permissions(&it.src_file_info.as_ref().unwrap().to_path(),
+                                                              &it.dest_file_info.to_path())
+                    .map_err(Into::into);
+                self.finish(it, op_started, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_touch_task(&self) -> Result<()> {
+        for items in self.decision.touch_items.values() {
+            for it in items {
+                if self._stopped.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                self.log_progress(&self._processed_count, it);
+                if self._dry_run {
+                    continue;
+                }
+                let op_started = Instant::now();
+                let result = self.context.fs
+                    .copy_times(&it.src_file_info.as_ref().unwrap().to_path(), &it.dest_file_info.to_path())
+                    .map_err(Into::into);
+                self.finish(it, op_started, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn execute_del_task(&self) -> Result<()> {
+        let started = Instant::now();
+        let mut deleted: u32 = 0;
+        for items in self.decision.del_items.values() {
+            for it in items {
+                if self._stopped.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                if let Some(rate) = self.context.delete_rate_limit.filter(|_| !self._dry_run) {
+                    // 按每秒删除数量限速，提前完成时等待到预期时间点
+                    let expected = Duration::from_secs_f64(deleted as f64 / rate);
+                    let elapsed = started.elapsed();
+                    if expected > elapsed {
+                        thread::sleep(expected - elapsed);
+                    }
+                    deleted += 1;
+                }
+                if self.context.protect_destination_created_during_run
+                    && created_since(self.context.fs.as_ref(), &it.dest_file_info.to_path(), self.context.run_started).unwrap_or(false) {
+                    self.print_line(&format!("跳过删除，运行期间有新建的内容: {}",
+                                             adjust_canonicalization(it.dest_file_info.absolute_dir_with_self())));
+                    continue;
+                }
+                if !self.confirm(it)? {
+                    continue;
+                }
+                self.log_progress(&self._processed_count, it);
+                if self._dry_run {
+                    continue;
+                }
+                let op_started = Instant::now();
+                let path = it.dest_file_info.to_path();
+                // 已随上层目录删除或被其他程序删掉的内容不再报错
+                if self.context.fs.symlink_metadata(&path).is_err_and(|e| e.kind() == io::ErrorKind::NotFound) {
+                    self.print_line(&format!("已不存在，跳过删除: {}",
+                                             adjust_canonicalization(it.dest_file_info.absolute_dir_with_self())));
+                    continue;
+                }
+                let dest_before = self.dest_stat_before(it);
+                let result = self.with_retries(it, |_| if let Some(trash_root) = self.context.trash_root() {
+                    self.move_to_trash(it, &trash_root)
+                } else {
+                    self.context.fs.remove(&path).map_err(Into::into)
+                });
+                self.audit(it, dest_before, &result)?;
+                self.finish(it, op_started, result)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 移入回收站本次运行的批次目录，保持原有的相对路径
+    fn move_to_trash(&self, item: &DecisionResultItem, trash_root: &Path) -> Result<()> {
+        let mut trash_path = trash_root.join(&self._trash_batch);
+        trash_path.push(item.dest_file_info.relative_path());
+        fs::create_dir_all(trash_path.parent().unwrap())?;
+        move_path(&item.dest_file_info.to_path(), &trash_path)
+    }
+
+    fn execute_update_task(&self) -> Result<()> {
+        self.for_each_parallel(self.decision.update_items.values().flatten().collect(), |it| {
+            if self.should_stop()? || !self.confirm(it)? {
+                return Ok(());
+            }
+            self.log_progress(&self._processed_count, it);
+            if self._dry_run {
+                return Ok(());
+            }
+            let op_started = Instant::now();
+            let dest_before = self.dest_stat_before(it);
+            let result = self.with_retries(it, |_| if it.reason == Some(UpdateReason::Appended) {
+                // 追加从目标文件当前的长度继续，重试时不会重复追加
+                let (src, dst) = (it.src_file_info.as_ref().unwrap().to_path(), it.dest_file_info.to_path());
+                let fs = self.context.fs.as_ref();
+                append_delta(fs, &src, &dst)
+                    // 追加后整个文件应与源文件一致
+                    .and_then(|_| if self.context.verify { verify_copy(fs, &src, &dst, self.context.hash_algo) } else { Ok(()) })
+                    .and_then(|_| copy_file_metadata(&src, &dst, &self.context))
+            } else {
+                copy_recursively(
+                    Path::new(&it.src_file_info.as_ref().unwrap().absolute_dir_with_self()),
+                    Path::new(&it.dest_file_info.absolute_dir_with_self()),
+                    true,
+                    &self.context,
+                )
+            });
+            self.audit(it, dest_before, &result)?;
+            self.finish(it, op_started, result)
+        })
+    }
+}
+
+// Function
+
+/// 读取配置文件。配置了 `pairs` 时每项是一组独立的同步目录（各自的 from、to、recursive），
+/// 其余配置各组共用；没有 `pairs` 时顶层的 from、to 即唯一的一组
+pub fn read_config(file_paths: &[String]) -> Result<Vec<SyncContext>> {
+    let mut builder = Config::builder();
+    for file_path in file_paths {
+        builder = builder.add_source(config::File::with_name(file_path));
+    }
+    read_settings(builder.build()?)
+}
+
+/// 从合并后的配置得到各组同步目录
+fn read_settings(settings: Config) -> Result<Vec<SyncContext>> {
+
+    /// 配置值的类型名，用于报错
+    fn kind_name(value: &Value) -> &'static str {
+        match value.kind {
+            ValueKind::Nil => "空值",
+            ValueKind::Boolean(_) => "布尔值",
+            ValueKind::I64(_) | ValueKind::I128(_) | ValueKind::U64(_) | ValueKind::U128(_) => "整数",
+            ValueKind::Float(_) => "浮点数",
+            ValueKind::String(_) => "字符串",
+            ValueKind::Table(_) => "表",
+            ValueKind::Array(_) => "数组",
+        }
+    }
+
+    /// 数字、布尔值也按字符串接受，空值、表、数组报错
+    fn expect_string(key: &str, value: Value) -> Result<String> {
+        match value.kind {
+            ValueKind::Nil | ValueKind::Table(_) | ValueKind::Array(_) =>
+                bail!("{} 应为字符串，但得到{}", key, kind_name(&value)),
+            _ => Ok(value.into_string()?),
+        }
+    }
+
+    fn expect_array(key: &str, value: Value) -> Result<Vec<Value>> {
+        match value.kind {
+            ValueKind::Array(array) => Ok(array),
+            _ => bail!("{} 应为数组，但得到{}", key, kind_name(&value)),
+        }
+    }
+
+    /// 只接受布尔值，`"yes"`、`1` 这样的字符串和数字也报错
+    fn expect_bool(key: &str, value: Value) -> Result<bool> {
+        match value.kind {
+            ValueKind::Boolean(value) => Ok(value),
+            _ => bail!("{} 应为布尔值，但得到{}", key, kind_name(&value)),
+        }
+    }
+
+    fn expect_table(key: &str, value: Value) -> Result<config::Map<String, Value>> {
+        match value.kind {
+            ValueKind::Table(table) => Ok(table),
+            _ => bail!("{} 应为表，但得到{}", key, kind_name(&value)),
+        }
+    }
+
+    /// 未配置时为 None，配置了但类型不对时报错
+    fn get_string(settings: &Config, key: &str) -> Result<Option<String>> {
+        settings.get::<Value>(key).ok().map(|value| expect_string(key, value)).transpose()
+    }
+
+    /// 未配置时为 None，配置了但不是非负整数（或超出范围）时报错
+    fn get_uint<T: TryFrom<i64>>(settings: &Config, key: &str) -> Result<Option<T>> {
+        let Ok(value) = settings.get::<Value>(key) else {
+            return Ok(None);
+        };
+        let number = match value.kind {
+            ValueKind::I64(_) | ValueKind::I128(_) | ValueKind::U64(_) | ValueKind::U128(_) =>
+                value.into_int().map_err(|_| anyhow!("{} 超出范围", key))?,
+            _ => bail!("{} 应为整数，但得到{}", key, kind_name(&value)),
+        };
+        Ok(Some(T::try_from(number).map_err(|_| anyhow!("{} 应为非负整数，但得到 {}", key, number))?))
+    }
+
+    /// 未配置时为 None，配置了但不是数字时报错
+    fn get_float(settings: &Config, key: &str) -> Result<Option<f64>> {
+        let Ok(value) = settings.get::<Value>(key) else {
+            return Ok(None);
+        };
+        match value.kind {
+            ValueKind::Float(_) | ValueKind::I64(_) | ValueKind::I128(_) | ValueKind::U64(_) | ValueKind::U128(_) =>
+                Ok(Some(value.into_float()?)),
+            _ => bail!("{} 应为数字，但得到{}", key, kind_name(&value)),
+        }
+    }
+
+    /// 未配置时取默认值；配置了但不是布尔值时给出警告，也取默认值
+    fn get_bool(settings: &Config, key: &str, default: bool) -> bool {
+        let value = match settings.get::<Value>(key) {
+            Ok(value) => value,
+            Err(_) => return default,
+        };
+        expect_bool(key, value).unwrap_or_else(|e| {
+            println!("警告: {}，按 {} 处理", e, default);
+            default
+        })
+    }
+
+    /// 未配置时取默认值；配置了但不是布尔值时报错。用于按默认值处理可能误删、误覆盖文件的选项
+    fn get_strict_bool(settings: &Config, key: &str, default: bool) -> Result<bool> {
+        settings.get::<Value>(key).ok().map_or(Ok(default), |value| expect_bool(key, value))
+    }
+
+    fn to_regex_vec(key: &str, value_vec: Option<Value>) -> Result<Vec<Regex>> {
+        Ok(match value_vec {
+            Some(value) => {
+                let mut vec = Vec::new();
+                for (i, v) in expect_array(key, value)?.into_iter().enumerate() {
+                    let reg_str = expect_string(&format!("{}[{}]", key, i), v)?;
+                    vec.push(Regex::new(reg_str.as_str())
+                        .with_context(|| format!("{}[{}] 正则无效: {}", key, i, reg_str))?)
+                }
+                vec
+            }
+            None => vec![]
+        })
+    }
+
+    fn to_glob_builder(key: &str, value_vec: Option<Value>) -> Result<GlobSetBuilder> {
+        let mut builder = GlobSetBuilder::new();
+        if let Some(value) = value_vec {
+            for (i, v) in expect_array(key, value)?.into_iter().enumerate() {
+                let glob_str = expect_string(&format!("{}[{}]", key, i), v)?;
+                builder.add(Glob::new(glob_str.as_str())
+                    .with_context(|| format!("{}[{}] glob 无效: {}", key, i, glob_str))?);
+            }
+        }
+        Ok(builder)
+    }
+
+    fn to_glob_set(key: &str, value_vec: Option<Value>) -> Result<GlobSet> {
+        Ok(to_glob_builder(key, value_vec)?.build()?)
+    }
+
+    /// path_map 的每一项：源目录下的相对路径、目标目录下的相对路径
+    fn to_path_map(value: Option<Value>) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut path_map = Vec::new();
+        if let Some(value) = value {
+            for (i, item) in expect_array("path_map", value)?.into_iter().enumerate() {
+                let key = format!("path_map[{}]", i);
+                let mut item = expect_table(&key, item)?;
+                let mut relative_path = |name: &str| -> Result<PathBuf> {
+                    let item_key = format!("{}.{}", key, name);
+                    let value = item.remove(name).ok_or_else(|| anyhow!("缺少 {}", item_key))?;
+                    let path = PathBuf::from(expect_string(&item_key, value)?.trim_matches('/'));
+                    if path.as_os_str().is_empty() || path.is_absolute() {
+                        bail!("{} 应为非空的相对路径", item_key);
+                    }
+                    Ok(path)
+                };
+                path_map.push((relative_path("from")?, relative_path("to")?));
+            }
+        }
+        Ok(path_map)
+    }
+
+    /// 按 path_map 拆分：每项映射单独成为一组，把源目录下的子目录同步到目标目录下映射的位置；
+    /// 每组都排除其源目录下另有映射的子目录，并保留目标目录下映射的位置不删除
+    fn apply_path_map(context: SyncContext, path_map: &[(PathBuf, PathBuf)],
+                      keep: &GlobSetBuilder) -> Result<Vec<SyncContext>> {
+        let mut contexts = vec![(PathBuf::new(), PathBuf::new(), context.clone())];
+        for (from, to) in path_map {
+            let mut mapped = context.clone();
+            mapped.from.path = Path::new(&context.from.path).join(from).to_str().unwrap().to_string();
+            mapped.to.path = Path::new(&context.to.path).join(to).to_str().unwrap().to_string();
+            contexts.push((from.clone(), to.clone(), mapped));
+        }
+        // 过滤规则匹配的是相对路径，统一用 `/` 分隔
+        let to_slash = |path: &Path| path.iter().map(|it| it.to_str().unwrap()).collect::<Vec<_>>().join("/");
+        let mut result = Vec::new();
+        for (from_prefix, to_prefix, mut context) in contexts {
+            let mut keep = keep.clone();
+            for (from, to) in path_map {
+                if let Some(rest) = from.strip_prefix(&from_prefix).ok().filter(|rest| !rest.as_os_str().is_empty()) {
+                    context.from.exclude.push(Regex::new(&format!("^{}$", regex::escape(&to_slash(rest))))?);
+                }
+                if let Some(rest) = to.strip_prefix(&to_prefix).ok().filter(|rest| !rest.as_os_str().is_empty()) {
+                    keep.add(Glob::new(&to_slash(rest))?);
+                }
+            }
+            context.keep = keep.build()?;
+            result.push(context);
+        }
+        Ok(result)
+    }
+
+    fn to_duration(key: &str, value: Option<Value>) -> Result<Option<Duration>> {
+        Ok(match value {
+            Some(value) => Some(parse_duration(&expect_string(key, value)?)?),
+            None => None,
+        })
+    }
+
+    fn to_size(key: &str, value: Option<Value>) -> Result<Option<u64>> {
+        Ok(match value {
+            Some(value) => Some(parse_size(&expect_string(key, value)?)?),
+            None => None,
+        })
+    }
+
+    /// from 块中的 recursive，优先于所在组和全局的设置
+    fn from_recursive(key: &str, value: &Option<Value>) -> Result<Option<bool>> {
+        match value.as_ref().map(|value| &value.kind) {
+            Some(ValueKind::Table(table)) => table.get("recursive")
+                .map(|value| expect_bool(&format!("{}.recursive", key), value.clone()))
+                .transpose(),
+            _ => Ok(None),
+        }
+    }
+
+    fn to_sync_path(key: &str, value: Option<Value>) -> Result<SyncPath> {
+        let mut settings = match value {
+            Some(value) => expect_table(key, value)?,
+            None => bail!("缺少 {}", key),
+        };
+        let path = match settings.remove("path") {
+            Some(value) => expand_env(&format!("{}.path", key), &expect_string(&format!("{}.path", key), value)?)?,
+            None => bail!("缺少 {}.path", key),
+        };
+        Ok(SyncPath {
+            path,
+            include: to_regex_vec(&format!("{}.include", key), settings.remove("include"))?,
+            exclude: to_regex_vec(&format!("{}.exclude", key), settings.remove("exclude"))?,
+            include_glob: to_glob_set(&format!("{}.include_glob", key), settings.remove("include_glob"))?,
+            exclude_glob: to_glob_set(&format!("{}.exclude_glob", key), settings.remove("exclude_glob"))?,
+            accessed_within: to_duration(&format!("{}.accessed_within", key), settings.remove("accessed_within"))?,
+            max_size: to_size(&format!("{}.max_size", key), settings.remove("max_size"))?,
+            min_size: to_size(&format!("{}.min_size", key), settings.remove("min_size"))?,
+            modified_after: to_duration(&format!("{}.modified_after", key), settings.remove("modified_after"))?,
+        })
+    }
+
+    let recursive = get_bool(&settings, "recursive", false);
+    let keep = to_glob_builder("keep", settings.get::<Value>("keep").ok())?;
+    let path_map = to_path_map(settings.get::<Value>("path_map").ok())?;
+    let pairs = match settings.get::<Value>("pairs") {
+        Ok(value) => {
+            let mut pairs = Vec::new();
+            for (i, pair) in expect_array("pairs", value)?.into_iter().enumerate() {
+                let key = format!("pairs[{}]", i);
+                let mut pair = expect_table(&key, pair)?;
+                let from = pair.remove("from");
+                let pair_recursive = match from_recursive(&format!("{}.from", key), &from)? {
+                    Some(from_recursive) => from_recursive,
+                    None => match pair.remove("recursive") {
+                        Some(value) => expect_bool(&format!("{}.recursive", key), value)?,
+                        None => recursive,
+                    },
+                };
+                pairs.push((
+                    to_sync_path(&format!("{}.from", key), from)?,
+                    to_sync_path(&format!("{}.to", key), pair.remove("to"))?,
+                    pair_recursive,
+                ));
+            }
+            if pairs.is_empty() {
+                bail!("pairs 不能为空");
+            }
+            pairs
+        }
+        Err(_) => {
+            let from = settings.get::<Value>("from").ok();
+            let pair_recursive = from_recursive("from", &from)?.unwrap_or(recursive);
+            vec![(
+                to_sync_path("from", from)?,
+                to_sync_path("to", settings.get::<Value>("to").ok())?,
+                pair_recursive,
+            )]
+        }
+    };
+
+    let context = SyncContext {
+        from: SyncPath::default(),
+        to: SyncPath::default(),
+        recursive,
+        ignore: None,
+        cache_hints: get_bool(&settings, "cache_hints", false),
+        threads: get_uint(&settings, "threads")?.unwrap_or(1),
+        allow_special_files: get_bool(&settings, "allow_special_files", false),
+        trash_retention_days: get_uint(&settings, "trash_retention_days")?,
+        trash_dir: get_string(&settings, "trash_dir")?
+            .map(|trash_dir| expand_env("trash_dir", &trash_dir)).transpose()?
+            .map(PathBuf::from),
+        approve_command: get_string(&settings, "approve_command")?,
+        pre_hook: get_string(&settings, "pre_hook")?,
+        post_hook: get_string(&settings, "post_hook")?,
+        force_copy: Vec::new(),
+        preserve_attributes: get_bool(&settings, "preserve_attributes", false),
+        preserve_times: get_bool(&settings, "preserve_times", true),
+        one_file_system: get_bool(&settings, "one_file_system", false),
+        resume_partial: get_bool(&settings, "resume_partial", false),
+        atomic_write: get_bool(&settings, "atomic_write", false),
+        log_file: get_string(&settings, "log_file")?
+            .map(|log_file| expand_env("log_file", &log_file)).transpose()?
+            .map(PathBuf::from),
+        keep: keep.build()?,
+        delete: get_strict_bool(&settings, "delete", true)?,
+        mode: match get_string(&settings, "mode")?.as_deref() {
+            None | Some("oneway") => SyncMode::ONEWAY,
+            Some("bidirectional") => SyncMode::BIDIRECTIONAL,
+            Some(other) => bail!("mode 只能是 oneway 或 bidirectional: {}", other),
+        },
+        last_synced: None,
+        state_db: get_bool(&settings, "state_db", false),
+        synced_files: None,
+        verify_tree_counts: get_bool(&settings, "verify_tree_counts", false),
+        progress_by_bytes: get_bool(&settings, "progress_by_bytes", false),
+        verify: get_bool(&settings, "verify", false),
+        content_filter: match settings.get::<Value>("content_filter") {
+            Ok(filter) => {
+                let mut filter = expect_table("content_filter", filter)?;
+                Some(ContentFilter {
+                    glob: Glob::new(&expect_string("content_filter.glob", filter.remove("glob")
+                        .ok_or_else(|| anyhow!("content_filter 缺少 glob"))?)?)?
+                        .compile_matcher(),
+                    command: expect_string("content_filter.command", filter.remove("command")
+                        .ok_or_else(|| anyhow!("content_filter 缺少 command"))?)?,
+                })
+            }
+            Err(_) => None,
+        },
+        fix_case: get_bool(&settings, "fix_case", false),
+        case_insensitive: get_bool(&settings, "case_insensitive", cfg!(windows)),
+        detect_renames: get_bool(&settings, "detect_renames", false),
+        delete_rate_limit: get_float(&settings, "delete_rate_limit")?.filter(|rate| *rate > 0.0),
+        retries: get_uint(&settings, "retries")?.unwrap_or(0),
+        symlinks: match get_string(&settings, "symlinks")? {
+            Some(policy) => match policy.as_str() {
+                "follow" => SymlinkPolicy::FOLLOW,
+                "skip" => SymlinkPolicy::SKIP,
+                "preserve" => SymlinkPolicy::PRESERVE,
+                _ => bail!("symlinks 只能是 follow、skip 或 preserve: {}", policy),
+            },
+            // 也接受 follow_symlinks: false，等同于 preserve
+            None => if get_bool(&settings, "follow_symlinks", true) {
+                SymlinkPolicy::FOLLOW
+            } else {
+                SymlinkPolicy::PRESERVE
+            },
+        },
+        min_free_space: match get_string(&settings, "min_free_space")? {
+            Some(size) => Some(parse_size(&size)?),
+            None => None,
+        },
+        max_bandwidth: match get_string(&settings, "max_bandwidth")? {
+            Some(rate) => Some(Arc::new(BandwidthLimiter::new(parse_size(&rate)?))),
+            None => None,
+        },
+        preserve_permissions: get_bool(&settings, "preserve_permissions", false),
+        append_only: get_bool(&settings, "append_only", false),
+        hash_algo: match get_string(&settings, "hash_algo")? {
+            Some(algo) => match algo.as_str() {
+                "blake3" => HashAlgo::BLAKE3,
+                "sha256" => HashAlgo::SHA256,
+                "xxh3" => HashAlgo::XXH3,
+                _ => bail!("hash_algo 只能是 blake3、sha256 或 xxh3: {}", algo),
+            },
+            None => HashAlgo::BLAKE3,
+        },
+        compare: match get_string(&settings, "compare")?.as_deref() {
+            None | Some("bytes") => CompareMode::MtimeThenBytes,
+            Some("hash") => CompareMode::Hash,
+            Some("content") => CompareMode::Content,
+            Some(other) => bail!("compare 只能是 bytes、hash 或 content: {}", other),
+        },
+        deep_compare: get_bool(&settings, "deep_compare", false)
+            || get_bool(&settings, "force_content_compare", false),
+        max_path_length: get_uint(&settings, "max_path_length")?,
+        long_path_error: match get_string(&settings, "on_long_path")?.as_deref() {
+            None | Some("skip") => false,
+            Some("error") => true,
+            Some(other) => bail!("on_long_path 只能是 skip 或 error: {}", other),
+        },
+        prime: false,
+        include_empty_dirs: false,
+        max_depth: None,
+        since: None,
+        protect_destination_created_during_run: get_strict_bool(&settings, "protect_destination_created_during_run", false)?,
+        run_started: SystemTime::now(),
+        usn_journal: get_bool(&settings, "usn_journal", false),
+        changed_dirs: None,
+        fs: Arc::new(RealFileSystem),
+    };
+
+    let vcs_regex = Regex::new(&format!(
+        r"[\\/]({})$",
+        VCS_DIR_NAMES.map(regex::escape).join("|")
+    ))?;
+    let skip_vcs = get_bool(&settings, "skip_vcs", false);
+    let mut contexts = Vec::new();
+    for (from, to, recursive) in pairs {
+        let ignore = load_ignore_file(Path::new(&from.path))?;
+        let mut context = SyncContext { from, to, recursive, ignore, ..context.clone() };
+        if skip_vcs {
+            context.from.exclude.push(vcs_regex.clone());
+            context.to.exclude.push(vcs_regex.clone());
+        }
+        contexts.extend(apply_path_map(context, &path_map, &keep)?);
+    }
+    Ok(contexts)
+}
+
+/// 源目录根下的忽略规则文件名
+const IGNORE_FILE_NAME: &str = ".ssyncignore";
+
+/// 读取源目录根下的 .ssyncignore，不存在时返回 None
+fn load_ignore_file(from_path: &Path) -> Result<Option<Gitignore>> {
+    let ignore_path = from_path.join(IGNORE_FILE_NAME);
+    if !ignore_path.is_file() {
+        return Ok(None);
+    }
+    let mut builder = GitignoreBuilder::new(from_path);
+    if let Some(e) = builder.add(&ignore_path) {
+        bail!("读取 {} 失败: {}", ignore_path.display(), e);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// `skip_vcs: true` 时两侧都会排除的版本控制元数据目录
+const VCS_DIR_NAMES: [&str; 4] = [".git", ".svn", ".hg", ".bzr"];
+
+/// --config-check：逐项打印检查结果，返回是否全部通过
+fn check_config(file_paths: &[String]) -> bool {
+    let contexts = match read_config(file_paths) {
+        Ok(contexts) => contexts,
+        Err(e) => {
+            println!("[错误] 配置解析失败: {:#}", e);
+            return false;
+        }
+    };
+    println!("[OK] 配置解析、正则与 glob 编译");
+    let mut ok = true;
+    for context in &contexts {
+        // 目标为压缩包时检查其所在目录
+        let to_dir = if ZipDestination::is_zip_path(&context.to.path) {
+            Path::new(&context.to.path).parent().filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        } else {
+            Path::new(&context.to.path)
+        };
+        for (key, dir) in [("from.path", Path::new(&context.from.path)), ("to.path", to_dir)] {
+            if dir.is_dir() {
+                println!("[OK] {} 是目录: {}", key, dir.display());
+            } else if dir.exists() {
+                println!("[错误] {} 不是目录: {}", key, dir.display());
+                ok = false;
+            } else {
+                println!("[错误] {} 不存在: {}", key, dir.display());
+                ok = false;
+            }
+        }
+        if let Err(e) = check_paths_not_overlapping(context) {
+            println!("[错误] {:#}", e);
+            ok = false;
+        }
+    }
+    println!("{}", if ok { "配置检查通过" } else { "配置检查未通过" });
+    ok
+}
+
+/// 源目录必须存在；目标不存在时稍后询问是否创建，但已存在时必须是目录（压缩包除外）
+fn check_paths_exist(context: &SyncContext) -> Result<()> {
+    let from = Path::new(&context.from.path);
+    if !from.exists() {
+        bail!("源目录不存在: {}", context.from.path);
+    }
+    if !from.is_dir() {
+        bail!("源路径不是目录: {}", context.from.path);
+    }
+    let to = Path::new(&context.to.path);
+    if to.exists() && !to.is_dir() && !ZipDestination::is_zip_path(&context.to.path) {
+        bail!("目标路径不是目录: {}", context.to.path);
+    }
+    Ok(())
+}
+
+/// 源目录和目标目录在解析符号链接后不能相同或互相包含，否则同步时会删改正在读取的文件
+fn check_paths_not_overlapping(context: &SyncContext) -> Result<()> {
+    let (from, to) = match (fs::canonicalize(&context.from.path), canonicalize_lenient(&context.to.path)) {
+        (Ok(from), Ok(to)) => (from, to),
+        // 源目录不存在时无从比较，交给后续流程处理
+        _ => return Ok(()),
+    };
+    if from == to {
+        bail!("源目录与目标目录实际指向同一位置，拒绝执行: {}", from.display());
+    }
+    if to.starts_with(&from) || from.starts_with(&to) {
+        bail!("源目录与目标目录互相包含，拒绝执行: {} <-> {}", from.display(), to.display());
+    }
+    // 放在源、目标目录内的回收站会被当作普通内容同步或删除
+    if let Some(trash_dir) = &context.trash_dir {
+        let trash_dir = canonicalize_lenient(trash_dir)?;
+        if trash_dir.starts_with(&from) || trash_dir.starts_with(&to) {
+            bail!("trash_dir 不能位于源目录或目标目录内: {}", trash_dir.display());
+        }
+    }
+    Ok(())
+}
+
+/// 规范化可能还不存在的路径：规范化已存在的最长前缀，再拼上其余部分
+fn canonicalize_lenient(path: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    match fs::canonicalize(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) if !parent.as_os_str().is_empty() =>
+                Ok(canonicalize_lenient(parent)?.join(name)),
+            _ => Ok(std::env::current_dir()?.join(path)),
+        },
+        other => other,
+    }
+}
+
+/// 目标目录不存在时创建它，首次同步到新位置无需手动建目录。
+/// 源目录不存在时直接报错，避免因为源路径写错而凭空创建目标目录。
+/// --yes 不询问时只有同时指定 --create-dest 才创建，以免目标路径写错时把整个源目录复制过去
+fn ensure_dest_exists(context: &SyncContext, create_dest: bool, yes: bool) -> Result<()> {
+    let to = Path::new(&context.to.path);
+    if to.exists() {
+        return Ok(());
+    }
+    if !Path::new(&context.from.path).is_dir() {
+        bail!("源目录不存在: {}", context.from.path);
+    }
+    if !create_dest && yes {
+        bail!("目标目录不存在: {}，需要创建时请加上 --create-dest", context.to.path);
+    }
+    if !create_dest {
+        check_continue(&format!("目标目录 {} 不存在，是否创建？", context.to.path), false)?;
+    }
+    fs::create_dir_all(to)?;
+    println!("已创建目标目录: {}", context.to.path);
+    Ok(())
+}
+
+/// 判断目录是否与根目录位于不同的文件系统。
+/// Unix 下比较设备号；Windows 下卷挂载点、联接都是重解析点，遇到即视为跨文件系统
+fn is_on_other_file_system(path: &Path, root: &Path) -> Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(fs::metadata(path)?.dev() != fs::metadata(root)?.dev())
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_REPARSE_POINT;
+        let _ = root;
+        Ok(fs::symlink_metadata(path)?.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = (path, root);
+        Ok(false)
+    }
+}
+
+/// 解析 `30d`、`12h` 这样的时长，单位支持 s、m、h、d、w，不带单位时按秒计
+fn parse_duration(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let (number, unit) = text.split_at(text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()));
+    let number: u64 = number.parse().map_err(|_| anyhow!("无法解析时长: {}", text))?;
+    let unit_secs = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!("无法解析时长: {}", text),
+    };
+    Ok(Duration::from_secs(number * unit_secs))
+}
+
+/// 解析 --since：`24h`、`7d` 这样的时长表示距现在多久以前，也可以是 RFC 3339 时间
+fn parse_since(text: &str) -> Result<SystemTime> {
+    if let Ok(duration) = parse_duration(text) {
+        return SystemTime::now().checked_sub(duration).ok_or_else(|| anyhow!("时长过大: {}", text));
+    }
+    let rfc3339 = Regex::new(
+        r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(?:\.\d+)?(?:[Zz]|([+-])(\d{2}):(\d{2}))$"
+    ).unwrap();
+    let caps = rfc3339.captures(text.trim())
+        .ok_or_else(|| anyhow!("无法解析时间，应为 24h、7d 这样的时长或 RFC 3339 时间: {}", text))?;
+    let field = |i: usize| caps[i].parse::<i64>().unwrap();
+    let (year, month, day, hour, minute, second) = (field(1), field(2), field(3), field(4), field(5), field(6));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        bail!("无法解析时间: {}", text);
+    }
+    // 公历日期距 1970-01-01 的天数，三月作为一年的第一个月，闰日落在年末
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let mut secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    if let Some(sign) = caps.get(7) {
+        let offset = field(8) * 3600 + field(9) * 60;
+        secs -= if sign.as_str() == "+" { offset } else { -offset };
+    }
+    Ok(if secs >= 0 {
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    })
+}
+
+/// 路径长度，按 UTF-16 编码单元计，与 Windows 的路径长度限制一致
+fn path_length(path: &str) -> usize {
+    path.encode_utf16().count()
+}
+
+/// 解析 `512M`、`10G` 这样的大小，单位支持 B、K、M、G、T（按 1024 换算），不带单位时按字节计
+fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let (number, unit) = text.split_at(text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len()));
+    let number: u64 = number.parse().map_err(|_| anyhow!("无法解析大小: {}", text))?;
+    let unit_bytes: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("无法解析大小: {}", text),
+    };
+    Ok(number * unit_bytes)
+}
+
+/// 展开路径中 `${VAR}` 和 `%VAR%` 形式的环境变量引用，变量未设置时报错
+fn expand_env(key: &str, text: &str) -> Result<String> {
+    let env_ref = Regex::new(r"\$\{([^}]+)\}|%([^%\s]+)%").unwrap();
+    let mut expanded = String::new();
+    let mut last = 0;
+    for caps in env_ref.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        let name = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        let value = std::env::var(name)
+            .map_err(|_| anyhow!("{} 引用的环境变量 {} 未设置: {}", key, name, text))?;
+        expanded.push_str(&text[last..whole.start()]);
+        expanded.push_str(&value);
+        last = whole.end();
+    }
+    expanded.push_str(&text[last..]);
+    Ok(expanded)
+}
+
+/// 两边同时扫描，任一边出错或扫描线程崩溃时返回注明是哪一边的错误
+pub fn get_dict_info(sync_context: &SyncContext, scan_concurrency: usize) -> Result<(DirectoryInfo, DirectoryInfo)> {
+    // 源、目标两边共用扫描名额
+    let limiter = Arc::new(ScanLimiter::new(scan_concurrency));
+
+    let context = sync_context.clone();
+    let src_limiter = limiter.clone();
+    let src_handle = thread::spawn(move || {
+        DirectoryInfo::load_all_file(
+            context.from.path.clone(),
+            true,
+            context.from.path.clone(),
+            &context,
+            &OperateDirection::FROM,
+            &src_limiter,
+            &[],
+        ).with_context(|| format!("扫描源目录失败: {}", context.from.path))
+    });
+
+    let context = sync_context.clone();
+    let to_handle = thread::spawn(move || {
+        DirectoryInfo::load_all_file(
+            context.to.path.clone(),
+            true,
+            context.to.path.clone(),
+            &context,
+            &OperateDirection::TO,
+            &limiter,
+            &[],
+        ).with_context(|| format!("扫描目标目录失败: {}", context.to.path))
+    });
+    let src_dict_info = src_handle.join().map_err(|e| anyhow!(
+        "扫描源目录失败: {}: {}", sync_context.from.path, panic_message(e.as_ref())));
+    let to_dict_info = to_handle.join().map_err(|e| anyhow!(
+        "扫描目标目录失败: {}: {}", sync_context.to.path, panic_message(e.as_ref())));
+    Ok((src_dict_info??, to_dict_info??))
+}
+
+/// 线程崩溃时携带的信息
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or_else(|| "扫描线程异常退出".to_string()),
+    }
+}
+
+/// 目标为 `.zip` 文件时的同步：压缩包内的条目相当于目标目录中的文件，
+/// 按条目记录的大小和 CRC32 判断是否需要更新
+struct ZipDestination;
+
+impl ZipDestination {
+    fn is_zip_path(path: &str) -> bool {
+        path.to_lowercase().ends_with(".zip")
+    }
+
+    fn make_decision(context: &SyncContext, scan_concurrency: usize) -> Result<DecisionResult> {
+        let src_dict_info = DirectoryInfo::load_all_file(
+            context.from.path.clone(),
+            true,
+            context.from.path.clone(),
+            context,
+            &OperateDirection::FROM,
+            &ScanLimiter::new(scan_concurrency),
+            &[],
+        )?;
+        let mut src_files = Vec::new();
+        ZipDestination::collect_files(&src_dict_info, context.recursive, &mut src_files);
+        let mut entries = ZipDestination::read_entries(Path::new(&context.to.path))?;
+        println!("已加载目录信息");
+
+        let mut decision_result = DecisionResult::for_context(context);
+        for src in src_files {
+            let relative_path = src.relative_path();
+            let entry_name = ZipDestination::entry_name(&relative_path);
+            let dest = Arc::new(ZipDestination::dest_file_info(context, &relative_path));
+            let (action, reason) = match entries.remove(&entry_name) {
+                None => (FileAction::ADD, None),
+                Some((size, _)) if size != fs::metadata(src.to_path())?.len() =>
+                    (FileAction::UPDATE, Some(UpdateReason::SizeDiffers)),
+                Some((_, crc32)) if crc32 != crc32_file(&src.to_path())? =>
+                    (FileAction::UPDATE, Some(UpdateReason::HashDiffers)),
+                Some(_) => continue,
+            };
+            let items = match action {
+                FileAction::ADD => &mut decision_result.add_items,
+                _ => &mut decision_result.update_items,
+            };
+            items.entry(src.relative_path_without_file()).or_default().push(DecisionResultItem {
+                action,
+                src_file_info: Some(src),
+                dest_file_info: dest,
+                reason,
+            });
+        }
+        // 剩下的条目源目录中已经没有了，关闭删除时原样保留在压缩包中
+        if !context.delete {
+            return Ok(decision_result);
+        }
+        for entry_name in entries.into_keys() {
+            let dest = Arc::new(ZipDestination::dest_file_info(context, &entry_name));
+            let (action, items) = if context.keep.is_match(&entry_name) {
+                (FileAction::KEEP, &mut decision_result.keep_items)
+            } else {
+                (FileAction::DEL, &mut decision_result.del_items)
+            };
+            items.entry(dest.relative_path_without_file()).or_default().push(DecisionResultItem {
+                action,
+                src_file_info: None,
+                dest_file_info: dest,
+                reason: None,
+            });
+        }
+        Ok(decision_result)
+    }
+
+    fn collect_files(dict_info: &DirectoryInfo, recursive: bool, files: &mut Vec<Arc<FileInfo>>) {
+        files.extend(dict_info.files.iter().cloned());
+        if recursive {
+            for sub_dir in dict_info.sub_dirs.iter() {
+                ZipDestination::collect_files(sub_dir, recursive, files);
+            }
+        }
+    }
+
+    /// 压缩包内的文件条目：条目名 -> (大小, CRC32)，压缩包不存在时为空
+    fn read_entries(zip_path: &Path) -> Result<HashMap<String, (u64, u32)>> {
+        let mut entries = HashMap::new();
+        if !zip_path.exists() {
+            return Ok(entries);
+        }
+        let mut archive = zip::ZipArchive::new(File::open(zip_path)?)?;
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i)?;
+            if !entry.is_dir() {
+                entries.insert(entry.name().to_string(), (entry.size(), entry.crc32()));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// 条目名统一使用 `/` 分隔
+    fn entry_name(relative_path: &str) -> String {
+        relative_path.replace('\\', "/")
+    }
+
+    /// 压缩包内条目对应的虚拟路径，只用于展示
+    fn dest_file_info(context: &SyncContext, entry_name: &str) -> FileInfo {
+        let path = Path::new(&context.to.path).join(entry_name);
+        FileInfo::new(
+            path.file_name().unwrap().to_str().unwrap().to_string(),
+            context.to.path.clone(),
+            path.parent().unwrap().to_str().unwrap().to_string(),
+        )
+    }
+
+    /// 写出一个新的压缩包：未变化的条目原样复制，新增和更新的条目从源文件压缩写入，
+    /// 删除的条目不再写入。全部写完后再替换原压缩包，中途失败不会损坏原有内容
+    fn execute(decision: &DecisionResult, context: &SyncContext) -> Result<()> {
+        println!("同步任务开始执行");
+        let zip_path = Path::new(&context.to.path);
+        if let Some(parent) = zip_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut part_name = zip_path.file_name().unwrap().to_os_string();
+        part_name.push(PARTIAL_SUFFIX);
+        let part_path = zip_path.with_file_name(part_name);
+
+        let replaced: HashSet<String> = decision.update_items.values()
+            .chain(decision.del_items.values())
+            .flatten()
+            .map(|item| ZipDestination::entry_name(&item.dest_file_info.relative_path()))
+            .collect();
+        let mut writer = zip::ZipWriter::new(File::create(&part_path)?);
+        if zip_path.exists() {
+            let mut archive = zip::ZipArchive::new(File::open(zip_path)?)?;
+            for i in 0..archive.len() {
+                let entry = archive.by_index_raw(i)?;
+                if !replaced.contains(entry.name()) {
+                    writer.raw_copy_file(entry)?;
+                }
+            }
+        }
+
+        let total_count = decision.total_count();
+        let mut processed_count = 0;
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(true);
+        for item in decision.add_items.values().chain(decision.update_items.values()).flatten() {
+            let src = item.src_file_info.as_ref().unwrap();
+            processed_count += 1;
+            println!("{}/{}  Copying - {} to {}", processed_count, total_count,
+                     src.absolute_dir_with_self(), item.dest_file_info.absolute_dir_with_self());
+            writer.start_file(ZipDestination::entry_name(&item.dest_file_info.relative_path()), options)?;
+            io::copy(&mut File::open(src.to_path())?, &mut writer)?;
+        }
+        for item in decision.del_items.values().flatten() {
+            processed_count += 1;
+            println!("{}/{}  Deleting - {}", processed_count, total_count,
+                     item.dest_file_info.absolute_dir_with_self());
+        }
+        writer.finish()?;
+        fs::rename(&part_path, zip_path)?;
+        println!("同步任务执行完毕");
+        Ok(())
+    }
+}
+
+/// 计算文件内容的 CRC32，与压缩包条目记录的值比较
+fn crc32_file(path: &Path) -> Result<u32> {
+    let mut reader = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// 执行报告，每完成一项操作立即写入一行，中途中断也能保留已完成的部分。
+/// 带 UTF-8 BOM，Excel 直接打开不会乱码
+struct CsvReport {
+    writer: Mutex<File>,
+}
+
+impl CsvReport {
+    const HEADER: [&'static str; 7] =
+        ["action", "relative_path", "source", "destination", "bytes", "duration_ms", "result"];
+
+    fn create(path: &str) -> Result<Self> {
+        let mut file = File::create(path)?;
+        file.write_all(b"\xEF\xBB\xBF")?;
+        file.write_all(CsvReport::to_row(&CsvReport::HEADER.map(String::from)).as_bytes())?;
+        Ok(Self { writer: Mutex::new(file) })
+    }
+
+    fn write_row(&self, fs: &dyn FileSystem, item: &DecisionResultItem, duration: Duration, result: &Result<()>) -> Result<()> {
+        let bytes = match (&item.action, &item.src_file_info) {
+            (FileAction::ADD | FileAction::UPDATE, Some(src)) => path_size(fs, &src.to_path()).unwrap_or(0),
+            _ => 0,
+        };
+        let row = CsvReport::to_row(&[
+            format!("{:?}", item.action),
+            item.dest_file_info.relative_path(),
+            item.src_file_info.as_ref().map(|it| it.absolute_dir_with_self()).unwrap_or_default(),
+            item.dest_file_info.absolute_dir_with_self(),
+            bytes.to_string(),
+            duration.as_millis().to_string(),
+            match result {
+                Ok(_) => String::from("ok"),
+                Err(e) => format!("error: {}", e),
+            },
+        ]);
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(row.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 含逗号、引号或换行的字段用双引号包起来，字段内的双引号写两次
+    fn to_row(fields: &[String]) -> String {
+        let mut row = fields.iter()
+            .map(|field| if field.contains(&[',', '"', '\n', '\r'][..]) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.clone()
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        row.push_str("\r\n");
+        row
+    }
+}
+
+/// log_file 审计日志，追加写入，每行一个 JSON 对象
+struct AuditLog {
+    writer: Mutex<File>,
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    /// 完成操作时的 Unix 时间（秒）
+    time: u64,
+    action: FileAction,
+    relative_path: String,
+    /// 源文件的大小、修改时间（Unix 秒），删除时为空
+    source_size: Option<u64>,
+    source_mtime: Option<i64>,
+    /// 覆盖或删除前目标文件的大小、修改时间，新增时为空
+    dest_size: Option<u64>,
+    dest_mtime: Option<i64>,
+}
+
+impl AuditLog {
+    fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("无法打开审计日志: {}", path.display()))?;
+        Ok(Self { writer: Mutex::new(file) })
+    }
+
+    fn write_entry(&self, fs: &dyn FileSystem, item: &DecisionResultItem, dest_before: Option<(u64, i64)>) -> Result<()> {
+        let source = item.src_file_info.as_ref().and_then(|src| file_stat(fs, &src.to_path()));
+        let entry = AuditEntry {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            action: item.action,
+            relative_path: item.dest_file_info.relative_path(),
+            source_size: source.map(|(size, _)| size),
+            source_mtime: source.map(|(_, mtime)| mtime),
+            dest_size: dest_before.map(|(size, _)| size),
+            dest_mtime: dest_before.map(|(_, mtime)| mtime),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(line.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// 文件（目录为其中文件的总和）的大小和修改时间（Unix 秒），不存在时为 None
+fn file_stat(fs: &dyn FileSystem, path: &Path) -> Option<(u64, i64)> {
+    let stat = fs.metadata(path).ok()?;
+    let size = path_size(fs, path).ok()?;
+    Some((size, FileTime::from_system_time(stat.modified).unix_seconds()))
+}
+
+/// 限制扫描时额外开启线程同时读取的目录数量，避免超大目录树占用过多内存和句柄
+#[derive(Debug)]
+struct ScanLimiter {
+    in_flight: AtomicUsize,
+    max: usize,
+}
+
+impl ScanLimiter {
+    fn new(max: usize) -> Self {
+        Self { in_flight: AtomicUsize::new(0), max }
+    }
+
+    /// 还有名额时占用一个并返回 true
+    fn try_acquire(&self) -> bool {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire,
+                          |n| if n < self.max { Some(n + 1) } else { None })
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// 对比两个文件的字节流，检查是否为同样的内容
+/// from: https://users.rust-lang.org/t/efficient-way-of-checking-if-two-files-have-the-same-content/74735
+fn is_same_file(fs: &dyn FileSystem, p1: &Path, p2: &Path) -> Result<bool> {
+    // Check if file sizes are different
+    if fs.metadata(p1)?.len != fs.metadata(p2)?.len {
+        return Ok(false);
+    }
+
+    // 按块读取比较，遇到不同的块立即返回
+    let (mut f1, mut f2) = (fs.open(p1)?, fs.open(p2)?);
+    let mut buf1 = vec![0u8; COMPARE_CHUNK_SIZE];
+    let mut buf2 = vec![0u8; COMPARE_CHUNK_SIZE];
+    loop {
+        let n1 = read_full(&mut f1, &mut buf1)?;
+        let n2 = read_full(&mut f2, &mut buf2)?;
+        if buf1[..n1] != buf2[..n2] {
+            return Ok(false);
+        }
+        if n1 == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// 比较文件内容时每次读取的块大小
+const COMPARE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// 尽量读满缓冲区，返回读到的字节数，只有到达末尾时才会少于缓冲区长度
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// --interactive 逐项确认的回答
+enum ItemAnswer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// 询问是否执行一项操作，y/yes、a/all、q/quit 之外的输入（包括直接回车）都视为跳过
+fn ask_item(hint: &str) -> Result<ItemAnswer> {
+    println!("{} [y/n/a/q]", hint);
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).context("无法读取标准输入")?;
+    Ok(match line.trim().to_lowercase().as_str() {
+        "y" | "yes" => ItemAnswer::Yes,
+        "a" | "all" => ItemAnswer::All,
+        "q" | "quit" => ItemAnswer::Quit,
+        _ => ItemAnswer::No,
+    })
+}
+
+/// 询问是否继续，`assume_yes` 时不读取标准输入直接继续。
+/// 只有输入 Y 或 YES（不区分大小写）才继续，其他输入包括直接回车都视为否，返回 `Declined` 错误
+fn check_continue(hint: &str, assume_yes: bool) -> Result<()> {
+    if assume_yes {
+        return Ok(());
+    }
+    println!("{} [Y/N]", hint);
+    let mut line = String::new();
+    let stdin = io::stdin();
+    stdin.lock().read_line(&mut line).context("无法读取标准输入")?;
+    let answer = line.trim().to_uppercase();
+    if answer != "Y" && answer != "YES" {
+        return Err(Declined.into());
+    }
+    Ok(())
+}
+
+/// 调用外部审批命令，返回是否批准
+fn approve_by_command(command: &str, decision: &DecisionResult) -> Result<bool> {
+    let mut child = shell_command(command).stdin(Stdio::piped()).spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    match stdin.write_all(decision.to_json()?.as_bytes()) {
+        // 命令可以不读完计划就给出结果
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        other => other?,
+    }
+    drop(stdin);
+    Ok(child.wait()?.success())
+}
+
+/// 执行 pre_hook、post_hook，操作数和两侧目录通过环境变量传给命令，退出码不为 0 时报错
+fn run_hook(key: &str, command: &str, context: &SyncContext, changed_count: usize) -> Result<()> {
+    println!("执行 {}: {}", key, command);
+    let status = shell_command(command)
+        .env("SSYNC_CHANGED_COUNT", changed_count.to_string())
+        .env("SSYNC_FROM", &context.from.path)
+        .env("SSYNC_TO", &context.to.path)
+        .status()
+        .with_context(|| format!("无法执行 {}: {}", key, command))?;
+    if !status.success() {
+        bail!("{} 执行失败（{}）: {}", key, status, command);
+    }
+    Ok(())
+}
+
+/// 通过系统 shell 执行命令
+fn shell_command(command: &str) -> Command {
+    let mut cmd;
+    if cfg!(windows) {
+        cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+    } else {
+        cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+    }
+    cmd
+}
+
+/// 预备结束，`assume_yes` 时不等待回车
+fn ready_to_exit(assume_yes: bool) {
+    if assume_yes {
+        return;
+    }
+    println!("按下回车键结束……");
+    let mut buf = [0];
+    let stdin = io::stdin();
+    stdin.lock().read_exact(&mut buf).ok();
+}
+
+/// 清理回收站中超过保留天数的批次
+fn purge_trash(trash_dir: &Path, retention_days: u64) -> Result<()> {
+    if !trash_dir.is_dir() {
+        return Ok(());
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let retention_secs = retention_days * 24 * 60 * 60;
+    for entry in fs::read_dir(trash_dir)? {
+        let entry = entry?;
+        // 批次目录以移入时的时间戳命名，名字无法解析时按修改时间算
+        let trashed_at = match entry.file_name().to_str().and_then(|name| name.parse::<u64>().ok()) {
+            Some(secs) => secs,
+            None => entry.metadata()?.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        if now.saturating_sub(trashed_at) <= retention_secs {
+            continue;
+        }
+        println!("清理过期回收站内容: {}", entry.path().display());
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// 移动文件或目录，跨卷无法直接改名时复制过去再删除原位置
+fn move_path(src: &Path, dst: &Path) -> Result<()> {
+    match fs::rename(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_tree(src, dst)?;
+            if src.is_dir() && !src.is_symlink() {
+                fs::remove_dir_all(src)?;
+            } else {
+                fs::remove_file(src)?;
+            }
+            Ok(())
+        }
+        other => Ok(other?),
+    }
+}
+
+/// 原样复制文件或目录树（符号链接重建为链接，保留修改时间），不做同步时的过滤和转换
+fn copy_tree(src: &Path, dst: &Path) -> Result<()> {
+    let metadata = src.symlink_metadata()?;
+    if metadata.is_symlink() {
+        create_symlink(&fs::read_link(src)?, dst)?;
+    } else if metadata.is_dir() {
+        fs::create_dir_all(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(src, dst)?;
+        copy_time(src, dst)?;
+    }
+    Ok(())
+}
+
+fn copy_recursively(src: impl AsRef<Path>, dst: impl AsRef<Path>, overwrite: bool,
+                    context: &SyncContext) -> Result<()> {
+    let (src, dst, fs) = (src.as_ref(), dst.as_ref(), context.fs.as_ref());
+    let is_symlink = fs.symlink_metadata(src)?.is_symlink;
+    // 新增目录中的符号链接没有经过扫描，在这里按同样的方式处理
+    if context.symlinks == SymlinkPolicy::SKIP && is_symlink {
+        return Ok(());
+    }
+    if context.symlinks == SymlinkPolicy::FOLLOW && is_symlink && fs.metadata(src).is_err() {
+        bail!("符号链接指向的目标不存在: {} -> {}", src.display(), fs.read_link(src)?.display());
+    }
+    let dst_exists = fs.symlink_metadata(dst).is_ok();
+    if context.symlinks == SymlinkPolicy::PRESERVE && is_symlink {
+        if dst_exists {
+            if !overwrite {
+                return Ok(());
+            }
+            fs.remove(dst)?;
+        }
+        fs.symlink(&fs.read_link(src)?, dst)?;
+    } else if fs.metadata(src)?.is_file {
+        if context.atomic_write && (overwrite || !dst_exists) {
+            copy_file_atomically(src, dst, context)?;
+        } else if dst_exists && overwrite {
+            fs.remove(dst)?;
+            copy_file(src, dst, context)?;
+            copy_file_metadata(src, dst, context)?;
+        } else if !dst_exists {
+            copy_file(src, dst, context)?;
+            // 复制时间等元数据
+            copy_file_metadata(src, dst, context)?;
+        }
+    } else {
+        if is_symlink_cycle(fs, src) {
+            println!("警告: 跳过指向上层目录、会形成循环的符号链接: {}", src.display());
+            return Ok(());
+        }
+        if !dst_exists {
+            fs.create_dir(dst)?;
+            // 文件夹的试过了修改不了时间
+        }
+        // 子项与顶层走同样的逻辑，文件同样遵循 overwrite 并复制修改时间等元数据
+        for path in fs.read_dir(src)? {
+            copy_recursively(&path, dst.join(path.file_name().unwrap()), overwrite, context)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 删除文件。保留了只读属性的目标文件在 Windows 上要先去掉只读才能删除
+fn force_remove_file(path: &Path) -> io::Result<()> {
+    clear_readonly(path)?;
+    fs::remove_file(path)
+}
+
+/// 去掉文件的只读属性。Windows 上只读的文件不能删除，也不能被改名覆盖
+fn clear_readonly(path: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    {
+        let mut permissions = fs::metadata(path)?.permissions();
+        if permissions.readonly() {
+            permissions.set_readonly(false);
+            fs::set_permissions(path, permissions)?;
+        }
+    }
+    #[cfg(not(windows))]
+    let _ = path;
+    Ok(())
+}
+
+/// atomic_write 时写入的临时文件的后缀
+const ATOMIC_TMP_SUFFIX: &str = ".ssync-tmp";
+
+/// atomic_write：复制到临时文件并同步元数据后，改名覆盖目标文件。同一个卷内改名是原子的
+fn copy_file_atomically(src: &Path, dst: &Path, context: &SyncContext) -> Result<()> {
+    let mut tmp_name = dst.file_name().unwrap().to_os_string();
+    tmp_name.push(ATOMIC_TMP_SUFFIX);
+    let tmp_path = dst.with_file_name(tmp_name);
+    let fs = context.fs.as_ref();
+    // 上次运行被中断时留下的临时文件
+    if fs.symlink_metadata(&tmp_path).is_ok() {
+        fs.remove(&tmp_path)?;
+    }
+    let result = copy_file(src, &tmp_path, context)
+        .and_then(|_| copy_file_metadata(src, &tmp_path, context))
+        .and_then(|_| if fs.symlink_metadata(dst).is_ok() { clear_readonly(dst).map_err(Into::into) } else { Ok(()) })
+        .and_then(|_| fs.rename(&tmp_path, dst).map_err(Into::into));
+    if result.is_err() {
+        let _ = fs.remove(&tmp_path);
+    }
+    result
+}
+
+/// 指向目录的符号链接是否指向它所在路径的某一级上层目录，跟随进入会形成循环
+fn is_symlink_cycle(fs: &dyn FileSystem, path: &Path) -> bool {
+    if !fs.symlink_metadata(path).is_ok_and(|stat| stat.is_symlink) {
+        return false;
+    }
+    let Ok(target) = fs.canonicalize(path) else {
+        return false;
+    };
+    path.ancestors().skip(1).any(|dir| fs.canonicalize(dir).is_ok_and(|dir| dir == target))
+}
+
+/// 创建指向 target 的符号链接
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// 创建指向 target 的符号链接，Windows 需要区分文件链接和目录链接
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    if link.parent().unwrap().join(target).is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// 核对复制出的目录树与源目录树的文件数量，不一致说明复制不完整
+fn verify_tree_count(src: &Path, dst: &Path) -> Result<()> {
+    if !src.is_dir() {
+        return Ok(());
+    }
+    let (src_count, dst_count) = (count_files(src)?, count_files(dst)?);
+    if src_count != dst_count {
+        bail!("目录复制不完整: {} 有 {} 个文件，{} 只有 {} 个",
+              src.display(), src_count, dst.display(), dst_count);
+    }
+    Ok(())
+}
+
+/// 文件的大小，目录则递归累加其下所有文件的大小
+fn path_size(fs: &dyn FileSystem, path: &Path) -> Result<u64> {
+    Ok(path_size_and_count(fs, path)?.0)
+}
+
+/// 文件的大小，或目录中所有文件的 (总大小, 文件数)
+fn path_size_and_count(fs: &dyn FileSystem, path: &Path) -> Result<(u64, usize)> {
+    let stat = fs.metadata(path)?;
+    if !stat.is_dir {
+        return Ok((stat.len, 1));
+    }
+    if is_symlink_cycle(fs, path) {
+        return Ok((0, 0));
+    }
+    let (mut size, mut count) = (0, 0);
+    for entry in fs.read_dir(path)? {
+        let (entry_size, entry_count) = path_size_and_count(fs, &entry)?;
+        size += entry_size;
+        count += entry_count;
+    }
+    Ok((size, count))
+}
+
+/// 路径（目录时包括其中任一项）是否在 since 之后创建
+fn created_since(fs: &dyn FileSystem, path: &Path, since: SystemTime) -> Result<bool> {
+    let stat = fs.symlink_metadata(path)?;
+    if stat.created >= since {
+        return Ok(true);
+    }
+    if stat.is_dir {
+        for entry in fs.read_dir(path)? {
+            if created_since(fs, &entry, since)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// 文件的创建时间，文件系统不支持时用 ctime 代替
+#[cfg(unix)]
+fn created_time(metadata: &fs::Metadata) -> SystemTime {
+    use std::os::unix::fs::MetadataExt;
+    metadata.created().unwrap_or_else(|_| {
+        UNIX_EPOCH + Duration::new(metadata.ctime() as u64, metadata.ctime_nsec() as u32)
+    })
+}
+
+/// 文件的创建时间
+#[cfg(not(unix))]
+fn created_time(metadata: &fs::Metadata) -> SystemTime {
+    metadata.created().unwrap_or(UNIX_EPOCH)
+}
+
+/// 上次同步完成时源目录所在卷的 USN 日志位置
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct UsnState {
+    journal_id: u64,
+    next_usn: i64,
+}
+
+impl UsnState {
+    #[cfg(windows)]
+    fn load(to_path: &str) -> Option<UsnState> {
+        let text = fs::read_to_string(Path::new(to_path).join(USN_STATE_FILE_NAME)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn save(&self, to_path: &str) -> Result<()> {
+        fs::write(Path::new(to_path).join(USN_STATE_FILE_NAME), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// 双向同步上次同步的开始时间（Unix 时间戳，秒）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BidirectionalState {
+    last_synced: u64,
+}
+
+impl BidirectionalState {
+    fn load(to_path: &str) -> Option<SystemTime> {
+        let text = fs::read_to_string(Path::new(to_path).join(BIDIRECTIONAL_STATE_FILE_NAME)).ok()?;
+        let state: BidirectionalState = serde_json::from_str(&text).ok()?;
+        Some(UNIX_EPOCH + Duration::from_secs(state.last_synced))
+    }
+
+    fn save(last_synced: SystemTime, to_path: &str) -> Result<()> {
+        let state = BidirectionalState { last_synced: last_synced.duration_since(UNIX_EPOCH)?.as_secs() };
+        fs::write(Path::new(to_path).join(BIDIRECTIONAL_STATE_FILE_NAME), serde_json::to_string(&state)?)?;
+        Ok(())
+    }
+}
+
+/// state_db 中一个文件在上次同步成功时的状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SyncedFile {
+    size: u64,
+    /// 修改时间（Unix 时间戳的秒和纳秒部分）
+    mtime: i64,
+    mtime_nanos: u32,
+    /// 内容的哈希（hash_algo），符号链接等非普通文件为 None
+    hash: Option<String>,
+}
+
+impl SyncedFile {
+    /// 保留的符号链接取链接本身的信息，其余取文件的信息
+    fn metadata(fs: &dyn FileSystem, info: &FileInfo) -> Result<FileStat> {
+        let path = info.to_path();
+        Ok(if info.link_target.is_some() { fs.symlink_metadata(&path)? } else { fs.metadata(&path)? })
+    }
+
+    /// 文件当前的状态，大小和修改时间都与上次相同时沿用上次的哈希，不再读取内容
+    fn read(fs: &dyn FileSystem, info: &FileInfo, previous: Option<&SyncedFile>, algo: HashAlgo) -> Result<SyncedFile> {
+        let metadata = SyncedFile::metadata(fs, info)?;
+        let mtime = FileTime::from_system_time(metadata.modified);
+        let mut synced = SyncedFile {
+            size: metadata.len,
+            mtime: mtime.unix_seconds(),
+            mtime_nanos: mtime.nanoseconds(),
+            hash: None,
+        };
+        synced.hash = match previous {
+            Some(previous) if previous.same_size_and_mtime(&synced) => previous.hash.clone(),
+            _ if metadata.is_file => Some(to_hex(info.content_hash(fs, algo)?)),
+            _ => None,
+        };
+        Ok(synced)
+    }
+
+    fn same_size_and_mtime(&self, other: &SyncedFile) -> bool {
+        self.size == other.size && self.mtime == other.mtime && self.mtime_nanos == other.mtime_nanos
+    }
+
+    /// 文件在上次同步后是否修改过：大小不同即修改过，只有修改时间不同时再比较哈希
+    fn is_changed(&self, fs: &dyn FileSystem, info: &FileInfo, algo: HashAlgo) -> Result<bool> {
+        let metadata = SyncedFile::metadata(fs, info)?;
+        let mtime = FileTime::from_system_time(metadata.modified);
+        if metadata.len != self.size {
+            return Ok(true);
+        }
+        if mtime.unix_seconds() == self.mtime && mtime.nanoseconds() == self.mtime_nanos {
+            return Ok(false);
+        }
+        Ok(match &self.hash {
+            Some(hash) if metadata.is_file => &to_hex(info.content_hash(fs, algo)?) != hash,
+            _ => true,
+        })
+    }
+}
+
+/// 读取目标目录下的 state_db，不存在时返回 None（首次同步）
+fn load_state_db(to_path: &str) -> Result<Option<HashMap<String, SyncedFile>>> {
+    let path = Path::new(to_path).join(STATE_DB_FILE_NAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(&path)?;
+    let synced_files = serde_json::from_str(&text)
+        .with_context(|| format!("无法读取同步状态: {}", path.display()))?;
+    Ok(Some(synced_files))
+}
+
+/// 同步成功后重新扫描源目录，记录其中每个文件的状态。冲突的文件沿用上次的记录
+fn save_state_db(context: &SyncContext, conflicts: &HashSet<String>) -> Result<()> {
+    // USN 日志只列出了有变动的目录，这里需要完整扫描
+    let context = SyncContext { changed_dirs: None, since: None, ..context.clone() };
+    let src_dict_info = DirectoryInfo::load_all_file(
+        context.from.path.clone(),
+        true,
+        context.from.path.clone(),
+        &context,
+        &OperateDirection::FROM,
+        &ScanLimiter::new(0),
+        &[],
+    ).with_context(|| format!("扫描源目录失败: {}", context.from.path))?;
+    let previous = context.synced_files.as_deref();
+    let mut synced_files = HashMap::new();
+    let mut pending_dirs = vec![&src_dict_info];
+    while let Some(dir) = pending_dirs.pop() {
+        pending_dirs.extend(dir.sub_dirs.iter().map(|it| it.as_ref()));
+        for it in dir.files.iter() {
+            let relative_path = it.relative_path();
+            if conflicts.contains(&relative_path) {
+                continue;
+            }
+            let previous = previous.and_then(|files| files.get(&relative_path));
+            let synced = SyncedFile::read(context.fs.as_ref(), it, previous, context.hash_algo)?;
+            synced_files.insert(relative_path, synced);
+        }
+    }
+    for path in conflicts {
+        if let Some(synced) = previous.and_then(|files| files.get(path)) {
+            synced_files.insert(path.clone(), synced.clone());
+        }
+    }
+    fs::write(Path::new(&context.to.path).join(STATE_DB_FILE_NAME), serde_json::to_string(&synced_files)?)?;
+    Ok(())
+}
+
+/// 同步完成后保存下次运行需要的状态：USN 日志位置、state_db、双向同步的开始时间。
+/// 有未处理的冲突时不更新同步时间，冲突的文件在 state_db 中沿用上次的记录，下次运行仍能发现这些冲突
+fn save_sync_state(context: &SyncContext, usn_state: Option<UsnState>, conflicts: &HashSet<String>) -> Result<()> {
+    if let Some(state) = usn_state {
+        state.save(&context.to.path)?;
+    }
+    if context.state_db {
+        save_state_db(context, conflicts)?;
+    }
+    if context.mode == SyncMode::BIDIRECTIONAL && conflicts.is_empty() {
+        BidirectionalState::save(context.run_started, &context.to.path)?;
+    }
+    Ok(())
+}
+
+/// 从上次保存的位置读取 USN 日志，找出源目录中有变动的目录，同时返回当前的日志位置。
+/// 日志不可用（无管理员权限、日志被重建或已被覆盖等）时打印原因，变动目录返回 None，即完整扫描
+#[cfg(windows)]
+fn usn_changed_dirs(context: &SyncContext) -> (Option<HashSet<PathBuf>>, Option<UsnState>) {
+    let from_path = match fs::canonicalize(&context.from.path) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("无法读取 USN 日志，将完整扫描: {}", e);
+            return (None, None);
+        }
+    };
+    let journal = match UsnJournal::open(&from_path) {
+        Ok(journal) => journal,
+        Err(e) => {
+            println!("无法读取 USN 日志，将完整扫描: {}", e);
+            return (None, None);
+        }
+    };
+    let current = UsnState { journal_id: journal.data.UsnJournalID, next_usn: journal.data.NextUsn };
+    let changed_dirs = match UsnState::load(&context.to.path) {
+        None => {
+            println!("没有保存的 USN 日志位置，将完整扫描");
+            None
+        }
+        Some(saved) => match journal.changed_dirs(saved, &from_path) {
+            Ok(dirs) => {
+                println!("USN 日志中有变动的目录: {} 个", dirs.len());
+                Some(dirs)
+            }
+            Err(e) => {
+                println!("{}，将完整扫描", e);
+                None
+            }
+        },
+    };
+    (changed_dirs, Some(current))
+}
+
+#[cfg(not(windows))]
+fn usn_changed_dirs(_context: &SyncContext) -> (Option<HashSet<PathBuf>>, Option<UsnState>) {
+    println!("usn_journal 仅在 Windows 上生效，将完整扫描");
+    (None, None)
+}
+
+/// 打开的卷句柄及其 USN 日志信息
+#[cfg(windows)]
+struct UsnJournal {
+    volume: windows_sys::Win32::Foundation::HANDLE,
+    data: windows_sys::Win32::System::Ioctl::USN_JOURNAL_DATA_V0,
+}
+
+#[cfg(windows)]
+impl UsnJournal {
+    /// 打开路径所在的卷并查询日志，需要管理员权限
+    fn open(path: &Path) -> Result<UsnJournal> {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::Foundation::{GENERIC_READ, INVALID_HANDLE_VALUE};
+        use windows_sys::Win32::Storage::FileSystem::{
+            CreateFileW, FILE_SHARE_READ, FILE_SHARE_WRITE, GetVolumePathNameW, OPEN_EXISTING,
+        };
+        use windows_sys::Win32::System::IO::DeviceIoControl;
+        use windows_sys::Win32::System::Ioctl::{FSCTL_QUERY_USN_JOURNAL, USN_JOURNAL_DATA_V0};
+
+        let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut volume_path = [0u16; 1024];
+        if unsafe { GetVolumePathNameW(wide_path.as_ptr(), volume_path.as_mut_ptr(), volume_path.len() as u32) } == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // `C:\`、`\\?\C:\` 或 `\\?\Volume{...}\` 形式的卷路径，转为 `\\.\C:` 形式的设备路径
+        let volume_path = String::from_utf16_lossy(&volume_path[..volume_path.iter().position(|&c| c == 0).unwrap()]);
+        let volume_path = volume_path.trim_end_matches('\\');
+        let device_path = format!(r"\\.\{}", volume_path.trim_start_matches(r"\\?\"));
+        let wide_device: Vec<u16> = device_path.encode_utf16().chain(Some(0)).collect();
+        let volume = unsafe {
+            CreateFileW(wide_device.as_ptr(), GENERIC_READ, FILE_SHARE_READ | FILE_SHARE_WRITE,
+                        std::ptr::null(), OPEN_EXISTING, 0, std::ptr::null_mut())
+        };
+        if volume == INVALID_HANDLE_VALUE {
+            bail!("打开卷 {} 失败: {}", device_path, io::Error::last_os_error());
+        }
+        let mut journal = UsnJournal { volume, data: USN_JOURNAL_DATA_V0::default() };
+        let mut bytes_returned = 0u32;
+        let ok = unsafe {
+            DeviceIoControl(journal.volume, FSCTL_QUERY_USN_JOURNAL, std::ptr::null(), 0,
+                            &mut journal.data as *mut _ as *mut _,
+                            std::mem::size_of::<USN_JOURNAL_DATA_V0>() as u32,
+                            &mut bytes_returned, std::ptr::null_mut())
+        };
+        if ok == 0 {
+            bail!("卷 {} 未开启 USN 日志: {}", device_path, io::Error::last_os_error());
+        }
+        Ok(journal)
+    }
+
+    /// 读取 `saved` 之后到打开时为止的记录，返回 `root` 下有变动的目录（相对路径，含各级父目录）
+    fn changed_dirs(&self, saved: UsnState, root: &Path) -> Result<HashSet<PathBuf>> {
+        use windows_sys::Win32::System::IO::DeviceIoControl;
+        use windows_sys::Win32::System::Ioctl::{FSCTL_READ_USN_JOURNAL, READ_USN_JOURNAL_DATA_V0, USN_RECORD_V2};
+
+        if saved.journal_id != self.data.UsnJournalID {
+            bail!("USN 日志已被重建");
+        }
+        if saved.next_usn < self.data.FirstUsn || saved.next_usn < self.data.LowestValidUsn {
+            bail!("上次同步后的 USN 日志记录已被覆盖");
+        }
+
+        // 记录中只有所在目录的文件编号，先收集去重，最后再解析为路径
+        let mut parent_ids = HashSet::new();
+        let mut buffer = vec![0u64; 64 * 1024 / 8];
+        let mut request = READ_USN_JOURNAL_DATA_V0 {
+            StartUsn: saved.next_usn,
+            ReasonMask: u32::MAX,
+            ReturnOnlyOnClose: 0,
+            Timeout: 0,
+            BytesToWaitFor: 0,
+            UsnJournalID: self.data.UsnJournalID,
+        };
+        while request.StartUsn < self.data.NextUsn {
+            let mut bytes_returned = 0u32;
+            let ok = unsafe {
+                DeviceIoControl(self.volume, FSCTL_READ_USN_JOURNAL,
+                                &request as *const _ as *const _,
+                                std::mem::size_of::<READ_USN_JOURNAL_DATA_V0>() as u32,
+                                buffer.as_mut_ptr() as *mut _, (buffer.len() * 8) as u32,
+                                &mut bytes_returned, std::ptr::null_mut())
+            };
+            if ok == 0 {
+                bail!("读取 USN 日志失败: {}", io::Error::last_os_error());
+            }
+            // 输出开头是下一次读取的起点，之后是依次排列的记录
+            let next_usn = buffer[0] as i64;
+            let bytes = unsafe { std::slice::from_raw_parts(buffer.as_ptr() as *const u8, bytes_returned as usize) };
+            let mut offset = 8;
+            while offset + std::mem::size_of::<USN_RECORD_V2>() <= bytes.len() {
+                let record = unsafe { &*(bytes.as_ptr().add(offset) as *const USN_RECORD_V2) };
+                if record.MajorVersion != 2 {
+                    bail!("不支持的 USN 记录版本: {}", record.MajorVersion);
+                }
+                parent_ids.insert(record.ParentFileReferenceNumber);
+                offset += record.RecordLength as usize;
+            }
+            if next_usn <= request.StartUsn {
+                break;
+            }
+            request.StartUsn = next_usn;
+        }
+
+        let mut dirs = HashSet::new();
+        for id in parent_ids {
+            // 目录已被删除时打不开，它的删除记录也会出现在上级目录中
+            let Some(path) = self.path_by_id(id) else { continue };
+            let Ok(relative) = path.strip_prefix(root) else { continue };
+            for dir in relative.ancestors() {
+                dirs.insert(dir.to_path_buf());
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// 按文件编号取得目录的完整路径（`\\?\` 形式，与 canonicalize 的结果一致）
+    fn path_by_id(&self, id: u64) -> Option<PathBuf> {
+        use std::os::windows::ffi::OsStringExt;
+        use windows_sys::Win32::Foundation::{CloseHandle, INVALID_HANDLE_VALUE};
+        use windows_sys::Win32::Storage::FileSystem::{
+            FILE_FLAG_BACKUP_SEMANTICS, FILE_ID_DESCRIPTOR, FILE_ID_DESCRIPTOR_0, FILE_NAME_NORMALIZED,
+            FILE_READ_ATTRIBUTES, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, FileIdType,
+            GetFinalPathNameByHandleW, OpenFileById, VOLUME_NAME_DOS,
+        };
+
+        let descriptor = FILE_ID_DESCRIPTOR {
+            dwSize: std::mem::size_of::<FILE_ID_DESCRIPTOR>() as u32,
+            Type: FileIdType,
+            Anonymous: FILE_ID_DESCRIPTOR_0 { FileId: id as i64 },
+        };
+        let handle = unsafe {
+            OpenFileById(self.volume, &descriptor, FILE_READ_ATTRIBUTES,
+                         FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                         std::ptr::null(), FILE_FLAG_BACKUP_SEMANTICS)
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return None;
+        }
+        let mut buffer = vec![0u16; 32 * 1024];
+        let len = unsafe {
+            GetFinalPathNameByHandleW(handle, buffer.as_mut_ptr(), buffer.len() as u32,
+                                      FILE_NAME_NORMALIZED | VOLUME_NAME_DOS)
+        };
+        unsafe { CloseHandle(handle) };
+        if len == 0 || len as usize >= buffer.len() {
+            return None;
+        }
+        Some(PathBuf::from(std::ffi::OsString::from_wide(&buffer[..len as usize])))
+    }
+}
+
+#[cfg(windows)]
+impl Drop for UsnJournal {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::CloseHandle(self.volume) };
+    }
+}
+
+/// 以十六进制显示哈希值
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 以 B、KiB、MiB、GiB、TiB 中合适的单位显示字节数
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// 带正负号显示字节数的变化
+fn format_signed_bytes(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_bytes(bytes.unsigned_abs()))
+}
+
+/// 递归统计目录下的文件数量
+fn count_files(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// 复制单个文件的内容
+fn copy_file(src: impl AsRef<Path>, dst: impl AsRef<Path>, context: &SyncContext) -> Result<()> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    // 转换后的内容本就与源文件不同，不参与校验
+    if let Some(filter) = context.content_filter_for(src) {
+        return filter.copy(src, dst);
+    }
+    if context.verify {
+        // 复制时顺带算出源文件哈希，校验只需再读一遍目标文件
+        let src_hash = if context.resume_partial {
+            copy_file_resumable(src, dst, context)?
+        } else {
+            copy_file_with_hash(src, dst, context)?
+        };
+        if hash_file(context.fs.as_ref(), dst, context.hash_algo)? != src_hash {
+            bail!("复制校验失败，目标文件与源文件内容不一致: {}", dst.display());
+        }
+        return Ok(());
+    }
+    if context.resume_partial {
+        copy_file_resumable(src, dst, context)?;
+        return Ok(());
+    }
+    if let Some(limiter) = &context.max_bandwidth {
+        return copy_file_throttled(context.fs.as_ref(), src, dst, limiter);
+    }
+    #[cfg(target_os = "linux")]
+    if context.cache_hints && context.fs.metadata(src)?.len >= CACHE_HINTS_MIN_SIZE {
+        return copy_file_with_cache_hints(src, dst);
+    }
+
+    context.fs.copy(src, dst)?;
+    Ok(())
+}
+
+/// 限速复制时每次读取的大小
+const THROTTLE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// max_bandwidth：所有复制共用的令牌桶，每秒补充 rate 个字节，最多积攒一秒的量。
+/// 令牌不够时先记账再等待，保证长期的平均速度不超过上限
+#[derive(Debug)]
+struct BandwidthLimiter {
+    rate: f64,
+    /// (可用的令牌数, 上次补充的时间)
+    state: Mutex<(f64, Instant)>,
+}
+
+impl BandwidthLimiter {
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self { rate, state: Mutex::new((rate, Instant::now())) }
+    }
+
+    /// 取用 n 个字节的令牌，不够时等待
+    fn acquire(&self, n: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let (tokens, refilled) = *state;
+            let tokens = (tokens + now.duration_since(refilled).as_secs_f64() * self.rate).min(self.rate) - n as f64;
+            *state = (tokens, now);
+            if tokens < 0.0 { Duration::from_secs_f64(-tokens / self.rate) } else { Duration::ZERO }
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// 按 max_bandwidth 限速的读取，每次最多读 THROTTLE_CHUNK_SIZE；limiter 为 None 时不限速
+struct ThrottledReader<'a, R> {
+    inner: R,
+    limiter: Option<&'a BandwidthLimiter>,
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limiter) = self.limiter else {
+            return self.inner.read(buf);
+        };
+        let len = buf.len().min(THROTTLE_CHUNK_SIZE);
+        let n = self.inner.read(&mut buf[..len])?;
+        limiter.acquire(n);
+        Ok(n)
+    }
+}
+
+/// 分块读写，按 max_bandwidth 限速复制
+fn copy_file_throttled(fs: &dyn FileSystem, src: &Path, dst: &Path, limiter: &BandwidthLimiter) -> Result<()> {
+    let mut reader = fs.open(src)?;
+    let mut writer = fs.create(dst, false)?;
+    io::copy(&mut ThrottledReader { inner: &mut reader, limiter: Some(limiter) }, &mut writer)?;
+    drop(writer);
+    fs.copy_permissions(src, dst)?;
+    Ok(())
+}
+
+/// 自行读写复制时的缓冲区大小
+const COPY_BUFFER_SIZE: usize = 256 * 1024;
+
+/// 单趟复制：读取源文件的同时写入目标文件并计算哈希，返回源文件的哈希
+fn copy_file_with_hash(src: &Path, dst: &Path, context: &SyncContext) -> Result<Vec<u8>> {
+    let fs = context.fs.as_ref();
+    let mut reader = fs.open(src)?;
+    let mut writer = fs.create(dst, false)?;
+    let mut hasher = Hasher::new(context.hash_algo);
+    let limiter = context.max_bandwidth.as_deref();
+    copy_and_hash(&mut ThrottledReader { inner: &mut reader, limiter }, &mut writer, &mut hasher)?;
+    drop(writer);
+    fs.copy_permissions(src, dst)?;
+    Ok(hasher.finalize())
+}
+
+fn copy_and_hash(reader: &mut impl Read, writer: &mut impl Write, hasher: &mut Hasher) -> Result<()> {
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// 续传临时文件的后缀，只有 ssync 会创建，不会与用户自己的 `.part` 文件混淆
+const PARTIAL_SUFFIX: &str = ".ssync-part";
+
+/// 先写入同目录下的 `.ssync-part` 临时文件，写完后再改名为目标文件，返回源文件的哈希。
+/// 若已存在上次中断留下的 `.ssync-part` 文件，且其内容与源文件同样长度前缀的哈希一致，则从断点处追加
+fn copy_file_resumable(src: &Path, dst: &Path, context: &SyncContext) -> Result<Vec<u8>> {
+    let algo = context.hash_algo;
+    let mut part_name = dst.file_name().unwrap().to_os_string();
+    part_name.push(PARTIAL_SUFFIX);
+    let part_path = dst.with_file_name(part_name);
+
+    let fs = context.fs.as_ref();
+    let mut reader = fs.open(src)?;
+    let src_len = fs.metadata(src)?.len;
+    let mut src_hasher = Hasher::new(algo);
+    let mut resume_offset = 0;
+    if let Ok(part_stat) = fs.metadata(&part_path) {
+        let part_len = part_stat.len;
+        if part_len > 0 && part_len <= src_len {
+            let part_hash = hash_prefix(&mut fs.open(&part_path)?, part_len, algo)?;
+            io::copy(&mut (&mut reader).take(part_len), &mut src_hasher)?;
+            if part_hash == src_hasher.finalize() {
+                resume_offset = part_len;
+            }
+        }
+    }
+
+    let mut writer = if resume_offset > 0 {
+        println!("从断点续传: {} ({}/{} 字节)", dst.display(), resume_offset, src_len);
+        // 计算前缀哈希后 reader 已经停在断点处
+        fs.create(&part_path, true)?
+    } else {
+        reader = fs.open(src)?;
+        src_hasher.reset();
+        fs.create(&part_path, false)?
+    };
+    let limiter = context.max_bandwidth.as_deref();
+    copy_and_hash(&mut ThrottledReader { inner: &mut reader, limiter }, &mut writer, &mut src_hasher)?;
+    drop(writer);
+    fs.sync(&part_path)?;
+
+    fs.copy_permissions(src, &part_path)?;
+    fs.rename(&part_path, dst)?;
+    Ok(src_hasher.finalize())
+}
+
+/// 把源文件中超出目标文件长度的部分追加到目标文件末尾
+fn append_delta(fs: &dyn FileSystem, src: &Path, dst: &Path) -> Result<()> {
+    let dst_len = fs.metadata(dst)?.len;
+    let mut reader = fs.open(src)?;
+    // 跳过目标文件已有的部分
+    io::copy(&mut (&mut reader).take(dst_len), &mut io::sink())?;
+    let mut writer = fs.create(dst, true)?;
+    io::copy(&mut reader, &mut writer)?;
+    drop(writer);
+    fs.sync(dst)?;
+    Ok(())
+}
+
+/// verify：重新读取源文件和目标文件，哈希不一致时报错
+fn verify_copy(fs: &dyn FileSystem, src: &Path, dst: &Path, algo: HashAlgo) -> Result<()> {
+    if hash_file(fs, dst, algo)? != hash_file(fs, src, algo)? {
+        bail!("复制校验失败，目标文件与源文件内容不一致: {}", dst.display());
+    }
+    Ok(())
+}
+
+/// 计算前 `len` 个字节的哈希
+fn hash_prefix(reader: &mut impl Read, len: u64, algo: HashAlgo) -> Result<Vec<u8>> {
+    let mut hasher = Hasher::new(algo);
+    io::copy(&mut reader.take(len), &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+fn hash_file(fs: &dyn FileSystem, path: &Path, algo: HashAlgo) -> Result<Vec<u8>> {
+    hash_prefix(&mut fs.open(path)?, u64::MAX, algo)
+}
+
+/// 哈希算法
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HashAlgo {
+    /// 默认，速度快
+    BLAKE3,
+    /// 便于与已有的校验文件互通
+    SHA256,
+    /// 非加密哈希，速度最快
+    XXH3,
+}
+
+/// 对各哈希算法实现的统一封装
+enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgo) -> Self {
+        match algo {
+            HashAlgo::BLAKE3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgo::SHA256 => Hasher::Sha256(<sha2::Sha256 as sha2::Digest>::new()),
+            HashAlgo::XXH3 => Hasher::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake3(hasher) => { hasher.update(data); }
+            Hasher::Sha256(hasher) => sha2::Digest::update(hasher, data),
+            Hasher::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    /// 当前已输入内容的摘要，不影响继续输入
+    fn finalize(&self) -> Vec<u8> {
+        match self {
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Sha256(hasher) => sha2::Digest::finalize(hasher.clone()).to_vec(),
+            Hasher::Xxh3(hasher) => hasher.digest128().to_be_bytes().to_vec(),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Hasher::Blake3(hasher) => { hasher.reset(); }
+            Hasher::Sha256(hasher) => sha2::Digest::reset(hasher),
+            Hasher::Xxh3(hasher) => hasher.reset(),
+        }
+    }
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 启用缓存提示的最小文件大小，小文件走系统的 fs::copy 即可
+#[cfg(target_os = "linux")]
+const CACHE_HINTS_MIN_SIZE: u64 = 64 * 1024 * 1024;
+#[cfg(target_os = "linux")]
+const CACHE_HINTS_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// 带缓存提示的流式复制：读取前声明顺序访问，边读边丢弃已读部分的页缓存，
+/// 写完后落盘并丢弃目标文件的页缓存，避免大文件同步挤占其他进程的缓存。
+/// 提示只是建议，调用失败不影响复制结果。
+#[cfg(target_os = "linux")]
+fn copy_file_with_cache_hints(src: &Path, dst: &Path) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut reader = File::open(src)?;
+    let mut writer = File::create(dst)?;
+    let (src_fd, dst_fd) = (reader.as_raw_fd(), writer.as_raw_fd());
+    unsafe {
+        libc::posix_fadvise(src_fd, 0, 0, libc::POSIX_FADV_SEQUENTIAL);
+    }
+
+    let mut buf = vec![0u8; CACHE_HINTS_CHUNK_SIZE];
+    let mut offset: libc::off_t = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+        unsafe {
+            libc::posix_fadvise(src_fd, offset, n as libc::off_t, libc::POSIX_FADV_DONTNEED);
+        }
+        offset += n as libc::off_t;
+    }
+
+    // 脏页写回后 DONTNEED 才能真正释放
+    writer.sync_data()?;
+    unsafe {
+        libc::posix_fadvise(dst_fd, 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+    fs::set_permissions(dst, reader.metadata()?.permissions())?;
+    Ok(())
+}
+
+/// 复制完成后同步时间，以及按配置同步文件属性
+fn copy_file_metadata(src: impl AsRef<Path>, dst: impl AsRef<Path>, context: &SyncContext) -> Result<()> {
+    if context.preserve_times {
+        context.fs.copy_times(src.as_ref(), dst.as_ref())?;
+    }
+    if context.preserve_attributes {
+        #[cfg(windows)]
+        copy_attributes(&src, &dst)?;
+    }
+    Ok(())
+}
+
+/// 同步的 Windows 文件属性。压缩、加密等属性无法通过 SetFileAttributesW 设置，不在其列
+#[cfg(windows)]
+const PRESERVED_ATTRIBUTES: u32 = {
+    use windows_sys::Win32::Storage::FileSystem::*;
+    FILE_ATTRIBUTE_READONLY | FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM
+        | FILE_ATTRIBUTE_ARCHIVE | FILE_ATTRIBUTE_NOT_CONTENT_INDEXED
+};
+
+/// 把源文件的属性位应用到目标文件，目标文件上其余可设置的属性保持不变
+#[cfg(windows)]
+fn copy_attributes(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_NORMAL, FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_TEMPORARY, SetFileAttributesW,
+    };
+
+    let src_attributes = fs::metadata(src.as_ref())?.file_attributes() & PRESERVED_ATTRIBUTES;
+    let kept_attributes = fs::metadata(dst.as_ref())?.file_attributes()
+        & (FILE_ATTRIBUTE_OFFLINE | FILE_ATTRIBUTE_TEMPORARY);
+    let attributes = match src_attributes | kept_attributes {
+        0 => FILE_ATTRIBUTE_NORMAL,
+        attributes => attributes,
+    };
+    let wide_path: Vec<u16> = dst.as_ref().as_os_str().encode_wide().chain(Some(0)).collect();
+    if unsafe { SetFileAttributesW(wide_path.as_ptr(), attributes) } == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// 同步访问时间和修改时间，Windows 下同时同步创建时间
+fn copy_time(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
+    let metadata = fs::metadata(src.as_ref())?;
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::{FileTimesExt, OpenOptionsExt};
+        use windows_sys::Win32::Storage::FileSystem::{FILE_FLAG_BACKUP_SEMANTICS, FILE_WRITE_ATTRIBUTES};
+
+        let times = fs::FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?)
+            .set_created(metadata.created()?);
+        // 只申请写属性的权限，只读文件也能设置；打开目录需要 FILE_FLAG_BACKUP_SEMANTICS
+        OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+            .open(dst.as_ref())?
+            .set_times(times)?;
+    }
+    #[cfg(not(windows))]
+    filetime::set_file_times(
+        dst.as_ref(),
+        FileTime::from_last_access_time(&metadata),
+        FileTime::from_last_modification_time(&metadata),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn adjust_canonicalization(p: String) -> String {
+    const VERBATIM_PREFIX: &str = r#"\\?\"#;
+    if p.starts_with(VERBATIM_PREFIX) {
+        p[VERBATIM_PREFIX.len()..].to_string()
+    } else {
+        p
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn adjust_canonicalization(p: String) -> String {
+    p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// 内存中的文件或目录
+    #[derive(Debug, Clone)]
+    struct MemoryFile {
+        content: Vec<u8>,
+        modified: SystemTime,
+        is_dir: bool,
+        mode: u32,
+    }
+
+    /// 只在内存中的文件系统，分析结果不受磁盘上的内容影响
+    #[derive(Debug, Default)]
+    struct MemoryFileSystem {
+        files: Mutex<HashMap<PathBuf, MemoryFile>>,
+        /// 读取过的目录，按读取顺序
+        read_dirs: Mutex<Vec<PathBuf>>,
+        /// 复制过的目标文件，按复制顺序
+        copied: Mutex<Vec<PathBuf>>,
+        /// 每次读取目录的耗时，用于观察并发扫描
+        read_dir_delay: Duration,
+        /// 正在读取的目录数，以及其最大值
+        reading_dirs: AtomicUsize,
+        max_reading_dirs: AtomicUsize,
+        /// 容量（字节），剩余空间为容量减去已有文件的大小。None 表示不限
+        capacity: Option<u64>,
+    }
+
+    impl MemoryFileSystem {
+        /// 添加文件，修改时间为 Unix 时间戳（秒），缺少的上层目录一并添加
+        fn add_file(&self, path: &str, content: &str, modified: u64) {
+            let path = Path::new(path);
+            self.add_dir(path.parent().unwrap().to_str().unwrap());
+            self.files.lock().unwrap().insert(path.to_path_buf(), MemoryFile {
+                content: content.as_bytes().to_vec(),
+                modified: UNIX_EPOCH + Duration::from_secs(modified),
+                is_dir: false,
+                mode: 0o644,
+            });
+        }
+
+        fn add_dir(&self, path: &str) {
+            let mut files = self.files.lock().unwrap();
+            for dir in Path::new(path).ancestors() {
+                files.entry(dir.to_path_buf()).or_insert(MemoryFile {
+                    content: Vec::new(),
+                    modified: UNIX_EPOCH,
+                    is_dir: true,
+                    mode: 0o755,
+                });
+            }
+        }
+
+        fn get(&self, path: &Path) -> io::Result<MemoryFile> {
+            self.files.lock().unwrap().get(path).cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.display().to_string()))
+        }
+    }
+
+    impl FileSystem for MemoryFileSystem {
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            self.get(path)?;
+            Ok(path.to_path_buf())
+        }
+
+        fn metadata(&self, path: &Path) -> io::Result<FileStat> {
+            let file = self.get(path)?;
+            Ok(FileStat {
+                len: file.content.len() as u64,
+                modified: file.modified,
+                accessed: None,
+                created: file.modified,
+                is_dir: file.is_dir,
+                is_file: !file.is_dir,
+                is_symlink: false,
+                mode: file.mode,
+            })
+        }
+
+        fn symlink_metadata(&self, path: &Path) -> io::Result<FileStat> {
+            self.metadata(path)
+        }
+
+        fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, path.display().to_string()))
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            self.read_dirs.lock().unwrap().push(path.to_path_buf());
+            let reading = self.reading_dirs.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_reading_dirs.fetch_max(reading, Ordering::SeqCst);
+            thread::sleep(self.read_dir_delay);
+            self.reading_dirs.fetch_sub(1, Ordering::SeqCst);
+            let mut entries: Vec<PathBuf> = self.files.lock().unwrap().keys()
+                .filter(|it| it.parent() == Some(path))
+                .cloned()
+                .collect();
+            entries.sort();
+            Ok(entries)
+        }
+
+        fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+            Ok(Box::new(io::Cursor::new(self.get(path)?.content)))
+        }
+
+        fn create(&self, path: &Path, append: bool) -> io::Result<Box<dyn Write + '_>> {
+            if append {
+                self.get(path)?;
+            } else {
+                self.get(path.parent().unwrap())?;
+                self.files.lock().unwrap().insert(path.to_path_buf(), MemoryFile {
+                    content: Vec::new(),
+                    modified: SystemTime::now(),
+                    is_dir: false,
+                    mode: 0o644,
+                });
+            }
+            Ok(Box::new(MemoryWriter { fs: self, path: path.to_path_buf() }))
+        }
+
+        fn sync(&self, path: &Path) -> io::Result<()> {
+            self.get(path).map(|_| ())
+        }
+
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            self.get(path.parent().unwrap())?;
+            self.files.lock().unwrap().insert(path.to_path_buf(), MemoryFile {
+                content: Vec::new(),
+                modified: SystemTime::now(),
+                is_dir: true,
+                mode: 0o755,
+            });
+            Ok(())
+        }
+
+        fn symlink(&self, _target: &Path, link: &Path) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, link.display().to_string()))
+        }
+
+        /// 与真实的复制一样，目标文件的修改时间为复制的时间
+        fn copy(&self, src: &Path, dst: &Path) -> io::Result<u64> {
+            let file = MemoryFile { modified: SystemTime::now(), ..self.get(src)? };
+            let len = file.content.len() as u64;
+            self.files.lock().unwrap().insert(dst.to_path_buf(), file);
+            self.copied.lock().unwrap().push(dst.to_path_buf());
+            Ok(len)
+        }
+
+        fn copy_times(&self, src: &Path, dst: &Path) -> io::Result<()> {
+            let modified = self.get(src)?.modified;
+            self.files.lock().unwrap().get_mut(dst)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, dst.display().to_string()))?
+                .modified = modified;
+            Ok(())
+        }
+
+        fn copy_permissions(&self, src: &Path, dst: &Path) -> io::Result<()> {
+            let mode = self.get(src)?.mode;
+            self.files.lock().unwrap().get_mut(dst)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, dst.display().to_string()))?
+                .mode = mode;
+            Ok(())
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            let file = self.get(from)?;
+            let mut files = self.files.lock().unwrap();
+            files.remove(from);
+            files.insert(to.to_path_buf(), file);
+            Ok(())
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            self.get(path)?;
+            self.files.lock().unwrap().retain(|it, _| !it.starts_with(path));
+            Ok(())
+        }
+
+        fn available_space(&self, path: &Path) -> io::Result<u64> {
+            self.get(path)?;
+            let Some(capacity) = self.capacity else {
+                return Ok(u64::MAX);
+            };
+            let used: u64 = self.files.lock().unwrap().values().map(|it| it.content.len() as u64).sum();
+            Ok(capacity.saturating_sub(used))
+        }
+    }
+
+    /// 写入时直接追加到内存文件系统中的文件末尾
+    struct MemoryWriter<'a> {
+        fs: &'a MemoryFileSystem,
+        path: PathBuf,
+    }
+
+    impl Write for MemoryWriter<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut files = self.fs.files.lock().unwrap();
+            let file = files.get_mut(&self.path)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, self.path.display().to_string()))?;
+            file.content.extend_from_slice(buf);
+            file.modified = SystemTime::now();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 以 /src 同步到 /dst 的配置，config 为追加的配置
+    fn memory_context(fs: Arc<MemoryFileSystem>, config: &str) -> Arc<SyncContext> {
+        let yaml = format!("from:\n  path: /src\nto:\n  path: /dst\nrecursive: true\n{}", config);
+        fs.add_dir("/src");
+        fs.add_dir("/dst");
+        Arc::new(read_yaml(&yaml).unwrap().remove(0).with_file_system(fs))
+    }
+
+    /// 从 YAML 文本读取配置
+    fn read_yaml(yaml: &str) -> Result<Vec<SyncContext>> {
+        read_settings(Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()?)
+    }
+
+    /// 扫描内存文件系统并分析
+    fn decide_with(context: &Arc<SyncContext>) -> DecisionResult {
+        let (from, to) = get_dict_info(context, 0).unwrap();
+        DecisionTask::new(Arc::new(from), Arc::new(to), context.clone()).make_decision().unwrap()
+    }
+
+    fn decide(fs: Arc<MemoryFileSystem>, config: &str) -> DecisionResult {
+        decide_with(&memory_context(fs, config))
+    }
+
+    /// 各项的目标相对路径和原因，按路径排序
+    fn entries(items: &HashMap<String, Vec<DecisionResultItem>>) -> Vec<(String, Option<UpdateReason>)> {
+        let mut entries: Vec<_> = items.values().flatten()
+            .map(|it| (it.dest_file_info.relative_path(), it.reason))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    fn paths(items: &HashMap<String, Vec<DecisionResultItem>>) -> Vec<String> {
+        entries(items).into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// 测试用的临时目录，结束时删除
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "ssync-test-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+            fs::create_dir_all(&path).unwrap();
+            Self(fs::canonicalize(path).unwrap())
+        }
+
+        fn path(&self, relative_path: &str) -> PathBuf {
+            self.0.join(relative_path)
+        }
+
+        /// 写入文件，缺少的上层目录一并创建
+        fn write(&self, relative_path: &str, content: impl AsRef<[u8]>) -> PathBuf {
+            let path = self.path(relative_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            path
+        }
+
+        fn read(&self, relative_path: &str) -> Vec<u8> {
+            fs::read(self.path(relative_path)).unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// 以临时目录下的 src 同步到 dst 的配置，config 为追加的配置
+    fn disk_context(dir: &TempDir, config: &str) -> Arc<SyncContext> {
+        fs::create_dir_all(dir.path("src")).unwrap();
+        let yaml = format!("from:\n  path: '{}'\nto:\n  path: '{}'\nrecursive: true\n{}",
+                           dir.path("src").display(), dir.path("dst").display(), config);
+        Arc::new(read_yaml(&yaml).unwrap().remove(0))
+    }
+
+    /// 把 config 追加到临时目录下 src 同步到 dst 的配置文件中，按命令行参数 args 运行
+    fn run_in(dir: &TempDir, config: &str, args: &[&str]) -> Result<ExitCode> {
+        let yaml = format!("from:\n  path: '{}'\nto:\n  path: '{}'\nrecursive: true\n{}",
+                           dir.path("src").display(), dir.path("dst").display(), config);
+        let file = dir.write("ssync.yml", yaml);
+        let file = file.to_str().unwrap();
+        run_with_args(Args::parse_from(["ssync", "-f", file].iter().chain(args)))
+    }
+
+    /// 目录下所有文件和目录的相对路径及内容（目录为 None）
+    fn tree(root: &Path) -> BTreeMap<PathBuf, Option<Vec<u8>>> {
+        fn walk(root: &Path, dir: &Path, tree: &mut BTreeMap<PathBuf, Option<Vec<u8>>>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                let relative_path = path.strip_prefix(root).unwrap().to_path_buf();
+                if path.is_dir() {
+                    tree.insert(relative_path, None);
+                    walk(root, &path, tree);
+                } else {
+                    tree.insert(relative_path, Some(fs::read(&path).unwrap()));
+                }
+            }
+        }
+        let mut tree = BTreeMap::new();
+        walk(root, root, &mut tree);
+        tree
+    }
+
+    #[test]
+    fn add_files_and_dirs_only_in_source() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/new.txt", "new", 100);
+        fs.add_file("/src/sub/inner.txt", "inner", 100);
+        fs.add_file("/src/both/added.txt", "added", 100);
+        fs.add_dir("/dst/both");
+        let result = decide(fs, "");
+        // 新目录整体新增，不再逐个列出其中的文件
+        assert_eq!(paths(&result.add_items), ["both/added.txt", "new.txt", "sub"]);
+        assert!(result.del_items.values().all(Vec::is_empty));
+        assert!(result.update_items.values().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn del_files_and_dirs_only_in_destination() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/same.txt", "same", 100);
+        fs.add_file("/dst/same.txt", "same", 100);
+        fs.add_file("/dst/stale.txt", "stale", 100);
+        fs.add_file("/dst/old/inner.txt", "inner", 100);
+        let result = decide(fs, "");
+        assert_eq!(paths(&result.del_items), ["old", "stale.txt"]);
+        assert!(result.add_items.values().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn keep_instead_of_del() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/dst/uploads/a.png", "a", 100);
+        fs.add_file("/dst/stale.txt", "stale", 100);
+        let result = decide(fs.clone(), "keep: ['uploads/**', 'uploads']");
+        assert_eq!(paths(&result.keep_items), ["uploads"]);
+        assert_eq!(paths(&result.del_items), ["stale.txt"]);
+
+        let result = decide(fs, "delete: false");
+        assert!(result.del_items.values().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn update_by_size_and_mtime() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/same.txt", "same", 100);
+        fs.add_file("/dst/same.txt", "same", 100);
+        fs.add_file("/src/size.txt", "longer", 200);
+        fs.add_file("/dst/size.txt", "short", 100);
+        fs.add_file("/src/mtime.txt", "new", 200);
+        fs.add_file("/dst/mtime.txt", "old", 100);
+        let result = decide(fs, "");
+        assert_eq!(entries(&result.update_items), [
+            ("mtime.txt".to_string(), Some(UpdateReason::MtimeDiffers)),
+            ("size.txt".to_string(), Some(UpdateReason::SizeDiffers)),
+        ]);
+    }
+
+    #[test]
+    fn deep_compare_reads_content() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/changed.txt", "new", 100);
+        fs.add_file("/dst/changed.txt", "old", 100);
+        fs.add_file("/src/touched.txt", "same", 200);
+        fs.add_file("/dst/touched.txt", "same", 100);
+        let result = decide(fs, "deep_compare: true");
+        assert_eq!(entries(&result.update_items), [
+            ("changed.txt".to_string(), Some(UpdateReason::ContentDiffers)),
+        ]);
+    }
+
+    #[test]
+    fn execute_through_file_system() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/new.txt", "new", 100);
+        fs.add_file("/src/sub/inner.txt", "inner", 100);
+        fs.add_file("/src/changed.txt", "longer", 200);
+        fs.add_file("/dst/changed.txt", "short", 100);
+        fs.add_file("/dst/old/inner.txt", "inner", 100);
+        let context = memory_context(fs.clone(), "");
+        DecisionExecuteTask::new(decide_with(&context), context.clone()).execute().unwrap();
+
+        for (path, content) in [("/dst/new.txt", "new"), ("/dst/sub/inner.txt", "inner"), ("/dst/changed.txt", "longer")] {
+            assert_eq!(fs.get(Path::new(path)).unwrap().content, content.as_bytes());
+        }
+        assert!(fs.get(Path::new("/dst/old")).is_err());
+        // 修改时间与源文件对齐，再次分析没有要做的事
+        assert_eq!(fs.get(Path::new("/dst/changed.txt")).unwrap().modified, UNIX_EPOCH + Duration::from_secs(200));
+        assert!(decide_with(&context).is_empty());
+    }
+
+    #[test]
+    fn copy_options_through_file_system() {
+        for config in ["verify: true", "resume_partial: true", "max_bandwidth: 1MB", "atomic_write: true"] {
+            let fs = Arc::new(MemoryFileSystem::default());
+            fs.add_file("/src/new.txt", "new", 100);
+            fs.add_file("/src/changed.txt", "longer", 200);
+            fs.add_file("/dst/changed.txt", "short", 100);
+            let context = memory_context(fs.clone(), config);
+            DecisionExecuteTask::new(decide_with(&context), context.clone()).execute().unwrap();
+            assert_eq!(fs.get(Path::new("/dst/new.txt")).unwrap().content, b"new", "{}", config);
+            assert_eq!(fs.get(Path::new("/dst/changed.txt")).unwrap().content, b"longer", "{}", config);
+            assert!(decide_with(&context).is_empty(), "{}", config);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn copy_with_cache_hints() {
+        let dir = TempDir::new();
+        // 跨越多个缓冲区，且结尾不对齐
+        let content: Vec<u8> = (0..CACHE_HINTS_CHUNK_SIZE * 3 + 17).map(|i| (i % 251) as u8).collect();
+        let src = dir.write("src/big.bin", &content);
+        copy_file_with_cache_hints(&src, &dir.path("big.bin")).unwrap();
+        assert_eq!(dir.read("big.bin"), content);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn special_files_excluded_by_default() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = TempDir::new();
+        dir.write("src/file.txt", "file");
+        let fifo = CString::new(dir.path("src/fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let result = decide_with(&disk_context(&dir, ""));
+        assert_eq!(paths(&result.add_items), ["file.txt"]);
+
+        let result = decide_with(&disk_context(&dir, "allow_special_files: true"));
+        assert_eq!(paths(&result.add_items), ["fifo", "file.txt"]);
+    }
+
+    #[test]
+    fn update_reason_in_summary() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/config.ini", "new", 100);
+        fs.add_file("/dst/config.ini", "old", 100);
+        fs.add_file("/src/data.txt", "new", 200);
+        fs.add_file("/dst/data.txt", "old", 100);
+        // 只有内容不同时需要比较内容才能发现
+        let summary = decide(fs.clone(), "deep_compare: true").summary();
+        assert!(summary.contains("config.ini [内容不同]"), "{}", summary);
+        assert!(summary.contains("data.txt [内容不同]"), "{}", summary);
+
+        // 不比较内容时，修改时间、内容都不同的文件按修改时间更新
+        let summary = decide(fs.clone(), "").summary();
+        assert!(!summary.contains("config.ini"), "{}", summary);
+        assert!(summary.contains("data.txt [修改时间不同]"), "{}", summary);
+
+        // compare: content 不看修改时间，总是比较哈希
+        let summary = decide(fs, "compare: content").summary();
+        assert!(summary.contains("config.ini [哈希不同]"), "{}", summary);
+        assert!(summary.contains("data.txt [哈希不同]"), "{}", summary);
+    }
+
+    #[test]
+    fn purge_trash_past_retention() {
+        let dir = TempDir::new();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let (old, recent) = (now - 10 * 24 * 60 * 60, now - 24 * 60 * 60);
+        dir.write(&format!("{}/{}/old.txt", TRASH_DIR_NAME, old), "old");
+        dir.write(&format!("{}/{}/recent.txt", TRASH_DIR_NAME, recent), "recent");
+        purge_trash(&dir.path(TRASH_DIR_NAME), 7).unwrap();
+        assert!(!dir.path(&format!("{}/{}", TRASH_DIR_NAME, old)).exists());
+        assert!(dir.path(&format!("{}/{}/recent.txt", TRASH_DIR_NAME, recent)).exists());
+    }
+
+    #[test]
+    fn accessed_within_excludes_stale_files() {
+        let dir = TempDir::new();
+        let month_ago = FileTime::from_system_time(SystemTime::now() - Duration::from_secs(60 * 24 * 60 * 60));
+        dir.write("src/recent.txt", "recent");
+        let stale = dir.write("src/stale.txt", "stale");
+        filetime::set_file_times(&stale, month_ago, month_ago).unwrap();
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let yaml = format!("from:\n  path: '{}'\n  accessed_within: 30d\nto:\n  path: '{}'\n",
+                           dir.path("src").display(), dir.path("dst").display());
+        let result = decide_with(&Arc::new(read_yaml(&yaml).unwrap().remove(0)));
+        assert_eq!(paths(&result.add_items), ["recent.txt"]);
+    }
+
+    #[test]
+    fn approve_command_rejects() {
+        let dir = TempDir::new();
+        dir.write("src/new.txt", "new");
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let e = run_in(&dir, "approve_command: exit 1", &["-y"]).unwrap_err();
+        assert!(e.to_string().contains("审批命令未通过"), "{}", e);
+        assert!(!dir.path("dst/new.txt").exists());
+
+        assert!(run_in(&dir, "approve_command: exit 0", &["-y"]).is_ok());
+        assert_eq!(dir.read("dst/new.txt"), b"new");
+    }
+
+    #[test]
+    fn run_options_applied_to_context() {
+        let args = Args::parse_from(["ssync", "--force-copy", "./sub/a.txt", "--prime", "--include-empty-dirs",
+                                     "--max-depth", "2"]);
+        let context = read_yaml("from:\n  path: /src\nto:\n  path: /dst").unwrap().remove(0);
+        let context = context_for_run(&args, &context, None);
+        assert_eq!(context.force_copy, [PathBuf::from("sub/a.txt")]);
+        assert!(context.prime);
+        assert!(context.include_empty_dirs);
+        assert_eq!(context.max_depth, Some(2));
+    }
+
+    #[test]
+    fn force_copy_bypasses_comparison() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        // 大小、修改时间都相同，比较时会被跳过
+        fs.add_file("/src/sub/corrupted.txt", "good", 100);
+        fs.add_file("/dst/sub/corrupted.txt", "bad!", 100);
+        fs.add_file("/src/same.txt", "same", 100);
+        fs.add_file("/dst/same.txt", "same", 100);
+        let mut context = (*memory_context(fs, "")).clone();
+        context.force_copy = vec![PathBuf::from("sub/corrupted.txt")];
+        let result = decide_with(&Arc::new(context));
+        assert_eq!(entries(&result.update_items), [
+            ("sub/corrupted.txt".to_string(), Some(UpdateReason::Forced)),
+        ]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn refuse_destination_linked_to_source() {
+        let dir = TempDir::new();
+        dir.write("src/file.txt", "file");
+        std::os::unix::fs::symlink(dir.path("src"), dir.path("dst")).unwrap();
+        let e = check_paths_not_overlapping(&disk_context(&dir, "")).unwrap_err();
+        assert!(e.to_string().contains("实际指向同一位置"), "{}", e);
+
+        std::os::unix::fs::symlink(dir.path("src"), dir.path("link")).unwrap();
+        let yaml = format!("from:\n  path: '{}'\nto:\n  path: '{}'\n",
+                           dir.path("src").display(), dir.path("link/inner").display());
+        let e = check_paths_not_overlapping(&read_yaml(&yaml).unwrap()[0]).unwrap_err();
+        assert!(e.to_string().contains("互相包含"), "{}", e);
+        assert_eq!(dir.read("src/file.txt"), b"file");
+    }
+
+    #[test]
+    fn exit_code_on_noop() {
+        let dir = TempDir::new();
+        dir.write("src/same.txt", "same");
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        run_in(&dir, "", &["-y"]).unwrap();
+        assert_eq!(run_in(&dir, "", &["-y", "--exit-nonzero-on-noop"]).unwrap(), ExitCode::from(NOOP_EXIT_CODE));
+        // 默认仍以 0 结束
+        assert_eq!(run_in(&dir, "", &["-y"]).unwrap(), ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn summary_truncated_past_limit() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        for i in 0..10 {
+            fs.add_file(&format!("/src/file{}.txt", i), "new", 100);
+        }
+        let result = decide(fs, "");
+        let options = SummaryOptions { max_lines: Some(3), absolute_paths: false, deletions_disabled: false };
+        let summary = result.summary_with_options(&options);
+        assert_eq!(summary.matches("\tfile").count(), 3, "{}", summary);
+        assert!(summary.contains("...以及其余 7 项"), "{}", summary);
+        // 不超过限制时全部列出
+        let options = SummaryOptions { max_lines: Some(10), ..options };
+        assert!(!result.summary_with_options(&options).contains("以及其余"));
+        // JSON 输出不截断
+        let plan: serde_json::Value = serde_json::from_str(&result.to_json().unwrap()).unwrap();
+        assert_eq!(plan["items"].as_array().unwrap().len(), 10);
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn preserve_windows_attributes() {
+        use std::os::windows::ffi::OsStrExt;
+        use std::os::windows::fs::MetadataExt;
+        use windows_sys::Win32::Storage::FileSystem::{FILE_ATTRIBUTE_ARCHIVE, FILE_ATTRIBUTE_HIDDEN, SetFileAttributesW};
+
+        let dir = TempDir::new();
+        let src = dir.write("src/hidden.txt", "hidden");
+        let attributes = FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_ARCHIVE;
+        let wide_path: Vec<u16> = src.as_os_str().encode_wide().chain(Some(0)).collect();
+        assert_ne!(unsafe { SetFileAttributesW(wide_path.as_ptr(), attributes) }, 0);
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let context = disk_context(&dir, "preserve_attributes: true");
+        DecisionExecuteTask::new(decide_with(&context), context.clone()).execute().unwrap();
+        let copied = fs::metadata(dir.path("dst/hidden.txt")).unwrap().file_attributes();
+        assert_eq!(copied & attributes, attributes);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn one_file_system_skips_mount_point() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TempDir::new();
+        dir.write("src/local/file.txt", "local");
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        // 指向另一个文件系统（tmpfs）上目录的链接，跟随后相当于一个挂载点
+        let other = Path::new("/dev/shm").join(dir.0.file_name().unwrap());
+        if fs::create_dir(&other).is_err()
+            || fs::metadata(&other).unwrap().dev() == fs::metadata(&dir.0).unwrap().dev() {
+            let _ = fs::remove_dir(&other);
+            println!("没有可用的其他文件系统，跳过");
+            return;
+        }
+        fs::write(other.join("mounted.txt"), "mounted").unwrap();
+        std::os::unix::fs::symlink(&other, dir.path("src/mount")).unwrap();
+        let result = decide_with(&disk_context(&dir, "one_file_system: true"));
+        let _ = fs::remove_dir_all(&other);
+        assert_eq!(paths(&result.add_items), ["local"]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn follow_symlinked_dir_inside_destination() {
+        let dir = TempDir::new();
+        dir.write("other/inner.txt", "inner");
+        fs::create_dir_all(dir.path("src")).unwrap();
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        std::os::unix::fs::symlink(dir.path("other"), dir.path("src/link")).unwrap();
+        let context = disk_context(&dir, "");
+        let result = decide_with(&context);
+        assert_eq!(paths(&result.add_items), ["link"]);
+        DecisionExecuteTask::new(result, context.clone()).execute().unwrap();
+        assert_eq!(dir.read("dst/link/inner.txt"), b"inner");
+        assert!(decide_with(&context).is_empty());
+    }
+
+    #[test]
+    fn resume_from_partial_file() {
+        let dir = TempDir::new();
+        let content: Vec<u8> = (0..COPY_BUFFER_SIZE * 2 + 5).map(|i| (i % 253) as u8).collect();
+        dir.write("src/big.bin", &content);
+        // 用户自己的 .part 文件是普通文件
+        dir.write("src/movie.part", "movie");
+        dir.write(&format!("dst/big.bin{}", PARTIAL_SUFFIX), &content[..COPY_BUFFER_SIZE + 3]);
+        let context = disk_context(&dir, "resume_partial: true\nverify: true");
+        let result = decide_with(&context);
+        // 续传文件不当作目标目录独有的文件删除
+        assert_eq!(paths(&result.add_items), ["big.bin", "movie.part"]);
+        assert!(result.del_items.values().all(Vec::is_empty));
+        DecisionExecuteTask::new(result, context).execute().unwrap();
+        assert_eq!(dir.read("dst/big.bin"), content);
+        assert_eq!(dir.read("dst/movie.part"), b"movie");
+        assert!(!dir.path(&format!("dst/big.bin{}", PARTIAL_SUFFIX)).exists());
+
+        // 前缀与源文件不一致时从头复制
+        let part = dir.write(&format!("dst/big.bin{}", PARTIAL_SUFFIX), "garbage");
+        let hash = copy_file_resumable(&dir.path("src/big.bin"), &dir.path("dst/big.bin"), &disk_context(&dir, "")).unwrap();
+        assert_eq!(hash, hash_file(&RealFileSystem, &dir.path("src/big.bin"), HashAlgo::BLAKE3).unwrap());
+        assert_eq!(dir.read("dst/big.bin"), content);
+        assert!(!part.exists());
+    }
+
+    #[test]
+    fn keep_glob_protects_destination_only_files() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/uploads/shared.png", "shared", 100);
+        fs.add_file("/dst/uploads/shared.png", "shared", 100);
+        fs.add_file("/dst/uploads/user.png", "user", 100);
+        fs.add_file("/dst/uploads/2024/old.png", "old", 100);
+        fs.add_file("/dst/stale.txt", "stale", 100);
+        let result = decide(fs.clone(), "keep: ['uploads/**']");
+        assert_eq!(paths(&result.keep_items), ["uploads/2024", "uploads/user.png"]);
+        assert_eq!(paths(&result.del_items), ["stale.txt"]);
+        assert!(result.summary().contains("保留"));
+
+        // 整个目录只在目标目录中时同样保留
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/dst/uploads/user.png", "user", 100);
+        let result = decide(fs, "keep: ['uploads/**']");
+        assert!(result.del_items.values().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn verify_tree_count_detects_partial_copy() {
+        let dir = TempDir::new();
+        for name in ["a.txt", "sub/b.txt", "sub/c.txt"] {
+            dir.write(&format!("src/new/{}", name), name);
+        }
+        let context = disk_context(&dir, "verify_tree_counts: true");
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        copy_recursively(dir.path("src/new"), dir.path("dst/new"), false, &context).unwrap();
+        verify_tree_count(&dir.path("src/new"), &dir.path("dst/new")).unwrap();
+
+        // 模拟复制中途丢失了一个文件
+        fs::remove_file(dir.path("dst/new/sub/c.txt")).unwrap();
+        let e = verify_tree_count(&dir.path("src/new"), &dir.path("dst/new")).unwrap_err();
+        assert!(e.to_string().contains("目录复制不完整"), "{}", e);
+    }
+
+    #[test]
+    fn byte_progress_reaches_100_percent() {
+        let dir = TempDir::new();
+        dir.write("src/tiny.txt", "t");
+        dir.write("src/sub/big.bin", vec![0u8; 5000]);
+        dir.write("src/changed.txt", "new content");
+        dir.write("dst/changed.txt", "old");
+        dir.write("dst/stale.txt", "stale");
+        let context = disk_context(&dir, "progress_by_bytes: true");
+        let task = DecisionExecuteTask::new(decide_with(&context), context.clone());
+        let decision = &task.decision;
+        // 与执行时的顺序相同：新增、更新，最后删除
+        let percents: Vec<f64> = [&decision.add_items, &decision.update_items, &decision.del_items].into_iter()
+            .flat_map(|items| items.values().flatten())
+            .map(|it| task.bytes_progress_prefix(it).trim().trim_end_matches('%').parse().unwrap())
+            .collect();
+        assert_eq!(percents.len(), 4);
+        assert!(percents.windows(2).all(|it| it[0] <= it[1]), "{:?}", percents);
+        assert!(percents[1] < 100.0, "{:?}", percents);
+        // 最后一项复制完成时正好 100%，删除不计字节
+        assert_eq!(percents[2], 100.0);
+        assert_eq!(percents[3], 100.0);
+        assert_eq!(task._processed_bytes.load(Ordering::Relaxed), task._total_bytes);
+    }
+
+    #[test]
+    fn single_pass_copy_and_hash() {
+        let dir = TempDir::new();
+        let content: Vec<u8> = (0..COPY_BUFFER_SIZE * 2 + 99).map(|i| (i * 7 % 256) as u8).collect();
+        let src = dir.write("src/data.bin", &content);
+        let hash = copy_file_with_hash(&src, &dir.path("data.bin"), &disk_context(&dir, "")).unwrap();
+        assert_eq!(hash, blake3::hash(&content).as_bytes());
+        assert_eq!(dir.read("data.bin"), content);
+
+        // verify 时复制得到的哈希用于校验目标文件
+        dir.write("src/other.bin", &content[..1000]);
+        let context = disk_context(&dir, "verify: true\nhash_algo: sha256");
+        copy_file(dir.path("src/other.bin"), dir.path("other.bin"), &context).unwrap();
+        assert_eq!(dir.read("other.bin"), &content[..1000]);
+    }
+
+    #[test]
+    fn skip_vcs_does_not_scan_git() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/.git/HEAD", "ref", 100);
+        fs.add_file("/src/.hg/store/data", "data", 100);
+        fs.add_file("/src/code.rs", "code", 100);
+        fs.add_file("/dst/.svn/entries", "entries", 100);
+        let result = decide(fs.clone(), "skip_vcs: true");
+        assert_eq!(paths(&result.add_items), ["code.rs"]);
+        // 目标目录中的版本控制目录同样排除，不会被删除
+        assert!(result.del_items.values().all(Vec::is_empty));
+        let read_dirs = fs.read_dirs.lock().unwrap();
+        assert!(read_dirs.iter().all(|dir| !dir.starts_with("/src/.git") && !dir.starts_with("/src/.hg")),
+                "{:?}", read_dirs);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn content_filter_transforms_matching_files() {
+        let dir = TempDir::new();
+        dir.write("src/notes.txt", "hello world\n");
+        dir.write("src/data.bin", "hello world\n");
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let context = disk_context(&dir, "content_filter:\n  glob: '*.txt'\n  command: tr a-z A-Z");
+        DecisionExecuteTask::new(decide_with(&context), context.clone()).execute().unwrap();
+        assert_eq!(dir.read("dst/notes.txt"), b"HELLO WORLD\n");
+        assert_eq!(dir.read("dst/data.bin"), b"hello world\n");
+        // 与转换后的结果比较，没有变化时不再更新
+        assert!(decide_with(&context).is_empty());
+
+        dir.write("src/notes.txt", "hello there\n");
+        let result = decide_with(&context);
+        assert_eq!(entries(&result.update_items), [("notes.txt".to_string(), Some(UpdateReason::ContentDiffers))]);
+    }
+
+    #[test]
+    fn create_nested_destination() {
+        let dir = TempDir::new();
+        dir.write("src/sub/file.txt", "file");
+        let yaml = format!("from:\n  path: '{}'\nto:\n  path: '{}'\nrecursive: true\n",
+                           dir.path("src").display(), dir.path("backup/nested/dst").display());
+        let file = dir.write("ssync.yml", yaml);
+        let args = |extra: &[&str]| Args::parse_from(["ssync", "-f", file.to_str().unwrap()].iter().chain(extra));
+        // --yes 不会自动创建
+        let e = run_with_args(args(&["-y"])).unwrap_err();
+        assert!(e.to_string().contains("--create-dest"), "{}", e);
+        assert!(!dir.path("backup").exists());
+
+        run_with_args(args(&["-y", "--create-dest"])).unwrap();
+        assert_eq!(dir.read("backup/nested/dst/sub/file.txt"), b"file");
+    }
+
+    #[test]
+    fn fix_case_renames_destination() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/Readme.TXT", "readme", 100);
+        fs.add_file("/dst/readme.txt", "readme", 100);
+        fs.add_file("/src/Other.txt", "newer", 100);
+        fs.add_file("/dst/other.txt", "old", 100);
+        let context = memory_context(fs.clone(), "fix_case: true\ncase_insensitive: true");
+        let result = decide_with(&context);
+        assert_eq!(paths(&result.rename_items), ["readme.txt"]);
+        // 内容不同的只按不区分大小写的名字对应后更新，不改名
+        assert_eq!(paths(&result.update_items), ["other.txt"]);
+        assert!(result.add_items.values().all(Vec::is_empty));
+        assert!(result.del_items.values().all(Vec::is_empty));
+
+        DecisionExecuteTask::new(result, context.clone()).execute().unwrap();
+        assert_eq!(fs.get(Path::new("/dst/Readme.TXT")).unwrap().content, b"readme");
+        assert!(fs.get(Path::new("/dst/readme.txt")).is_err());
+    }
+
+    #[test]
+    fn later_config_file_overrides() {
+        let dir = TempDir::new();
+        let base = dir.write("base.yml", "from:\n  path: /data\n  exclude: ['\\.tmp$']\nto:\n  path: /backup\nrecursive: true\n");
+        let local = dir.write("local.yml", "to:\n  path: /mnt/backup\n");
+        let contexts = read_config(&[base.to_str().unwrap().to_string(), local.to_str().unwrap().to_string()]).unwrap();
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(contexts[0].to.path, "/mnt/backup");
+        // 未覆盖的配置沿用前面的文件
+        assert_eq!(contexts[0].from.path, "/data");
+        assert_eq!(contexts[0].from.exclude.len(), 1);
+        assert!(contexts[0].recursive);
+    }
+
+    #[test]
+    fn delete_rate_limit_paces_deletions() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        for i in 0..5 {
+            fs.add_file(&format!("/dst/stale{}.txt", i), "stale", 100);
+        }
+        let context = memory_context(fs.clone(), "delete_rate_limit: 20");
+        let started = Instant::now();
+        DecisionExecuteTask::new(decide_with(&context), context.clone()).execute().unwrap();
+        // 第一项立即删除，其余每项间隔 1/20 秒
+        assert!(started.elapsed() >= Duration::from_millis(200), "{:?}", started.elapsed());
+        assert_eq!(fs.read_dir(Path::new("/dst")).unwrap(), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_target_in_summary() {
+        let dir = TempDir::new();
+        dir.write("src/target.txt", "target");
+        std::os::unix::fs::symlink("target.txt", dir.path("src/link")).unwrap();
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let context = disk_context(&dir, "symlinks: preserve");
+        let result = decide_with(&context);
+        let summary = result.summary();
+        assert!(summary.contains("link -> target.txt"), "{}", summary);
+        DecisionExecuteTask::new(result, context).execute().unwrap();
+        assert_eq!(fs::read_link(dir.path("dst/link")).unwrap(), Path::new("target.txt"));
+    }
+
+    #[test]
+    fn stop_when_free_space_below_floor() {
+        let fs = Arc::new(MemoryFileSystem { capacity: Some(200), ..Default::default() });
+        // 源文件占用 80 字节，每复制一个剩余空间减少 20 字节
+        for i in 0..4 {
+            fs.add_file(&format!("/src/file{}.txt", i), &"x".repeat(20), 100);
+        }
+        let context = memory_context(fs.clone(), "min_free_space: 70B");
+        let completed = DecisionExecuteTask::new(decide_with(&context), context.clone()).execute().unwrap();
+        // 复制三个后剩余 60 字节，低于下限，其余操作不再执行
+        assert!(!completed);
+        assert_eq!(fs.read_dir(Path::new("/dst")).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn free_space_query_error_reported() {
+        let fs = Arc::new(MemoryFileSystem { capacity: Some(200), ..Default::default() });
+        fs.add_file("/src/file.txt", "file", 100);
+        let context = memory_context(fs.clone(), "min_free_space: 70B");
+        let result = decide_with(&context);
+        // 分析后目标目录消失，无法获取剩余空间
+        fs.remove(Path::new("/dst")).unwrap();
+        let e = DecisionExecuteTask::new(result, context).execute().unwrap_err();
+        assert!(format!("{:#}", e).contains("无法获取目标目录剩余空间: /dst"), "{:#}", e);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn permission_difference_reported() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        dir.write("src/script.sh", "echo");
+        fs::set_permissions(dir.path("src/script.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let context = disk_context(&dir, "");
+        DecisionExecuteTask::new(decide_with(&context), context.clone()).execute().unwrap();
+        fs::set_permissions(dir.path("dst/script.sh"), fs::Permissions::from_mode(0o600)).unwrap();
+        let mode = || fs::metadata(dir.path("dst/script.sh")).unwrap().permissions().mode() & 0o777;
+
+        // 只报告，不计入操作数，也不修改
+        let result = decide_with(&context);
+        assert_eq!(entries(&result.permission_items), [("script.sh".to_string(), Some(UpdateReason::PermissionDiffers))]);
+        assert!(result.summary().contains("script.sh [权限不同]"), "{}", result.summary());
+        assert!(result.is_empty());
+        DecisionExecuteTask::new(result, context).execute().unwrap();
+        assert_eq!(mode(), 0o600);
+
+        let context = disk_context(&dir, "preserve_permissions: true");
+        let result = decide_with(&context);
+        assert_eq!(result.total_count(), 1);
+        DecisionExecuteTask::new(result, context).execute().unwrap();
+        assert_eq!(mode(), 0o755);
+    }
+
+    #[test]
+    fn scan_concurrency_bounded() {
+        for concurrency in [0, 1, 3] {
+            let fs = Arc::new(MemoryFileSystem { read_dir_delay: Duration::from_millis(2), ..Default::default() });
+            for i in 0..8 {
+                for j in 0..3 {
+                    fs.add_file(&format!("/src/dir{}/sub{}/file.txt", i, j), "file", 100);
+                    fs.add_file(&format!("/dst/dir{}/sub{}/file.txt", i, j), "file", 100);
+                }
+            }
+            let context = memory_context(fs.clone(), "");
+            get_dict_info(&context, concurrency).unwrap();
+            // 源、目标各自的扫描线程之外，最多再有 concurrency 个线程同时读取目录
+            let max = fs.max_reading_dirs.load(Ordering::SeqCst);
+            assert!(max <= concurrency + 2, "concurrency {}: {}", concurrency, max);
+        }
+    }
+
+    #[test]
+    fn csv_report_rows_escaped() {
+        let dir = TempDir::new();
+        dir.write("src/a,b.txt", "comma");
+        dir.write("dst/stale.txt", "stale");
+        let context = disk_context(&dir, "");
+        let report = Arc::new(CsvReport::create(dir.path("report.csv").to_str().unwrap()).unwrap());
+        DecisionExecuteTask::new(decide_with(&context), context).with_csv_report(report).execute().unwrap();
+
+        let csv = String::from_utf8(dir.read("report.csv")).unwrap();
+        let lines: Vec<&str> = csv.trim_start_matches('\u{feff}').split_terminator("\r\n").collect();
+        assert_eq!(lines[0], "action,relative_path,source,destination,bytes,duration_ms,result");
+        assert_eq!(lines.len(), 3, "{}", csv);
+        let add = lines.iter().find(|line| line.starts_with("ADD,")).unwrap();
+        assert!(add.starts_with("ADD,\"a,b.txt\",\""), "{}", add);
+        assert!(add.contains(",5,"), "{}", add);
+        assert!(add.ends_with(",ok"), "{}", add);
+        assert!(lines.iter().any(|line| line.starts_with("DEL,stale.txt,,") && line.ends_with(",ok")));
+
+        assert_eq!(CsvReport::to_row(&["say \"hi\"".to_string(), "line\nbreak".to_string(), "plain".to_string()]),
+                   "\"say \"\"hi\"\"\",\"line\nbreak\",plain\r\n");
+    }
+
+    #[test]
+    fn append_only_appends_delta() {
+        let dir = TempDir::new();
+        dir.write("src/app.log", "line 1\nline 2\n");
+        dir.write("dst/app.log", "line 1\n");
+        dir.write("src/old.log", "line 1\n");
+        dir.write("dst/old.log", "line 1\nline 2\n");
+        let context = disk_context(&dir, "append_only: true");
+        let decision = decide_with(&context);
+        assert_eq!(entries(&decision.update_items), vec![("app.log".to_string(), Some(UpdateReason::Appended))]);
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+            fs::metadata(dir.path("dst/app.log")).unwrap().ino()
+        };
+        DecisionExecuteTask::new(decision, context).execute().unwrap();
+
+        assert_eq!(dir.read("dst/app.log"), b"line 1\nline 2\n");
+        // 目标已包含源的内容时不动
+        assert_eq!(dir.read("dst/old.log"), b"line 1\nline 2\n");
+        // 追加写入原文件，而不是复制整个文件后替换
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(fs::metadata(dir.path("dst/app.log")).unwrap().ino(), inode);
+        }
+    }
+
+    #[test]
+    fn append_only_rewritten_source_copied() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        // 源文件被改写，长度不变或变短，目标不再是源的前缀
+        fs.add_file("/src/same.log", "line X\n", 200);
+        fs.add_file("/dst/same.log", "line 1\n", 100);
+        fs.add_file("/src/short.log", "new\n", 200);
+        fs.add_file("/dst/short.log", "line 1\nline 2\n", 100);
+        let context = memory_context(fs.clone(), "append_only: true");
+        let decision = decide_with(&context);
+        assert_eq!(entries(&decision.update_items), [
+            ("same.log".to_string(), Some(UpdateReason::MtimeDiffers)),
+            ("short.log".to_string(), Some(UpdateReason::SizeDiffers)),
+        ]);
+        DecisionExecuteTask::new(decision, context).execute().unwrap();
+        assert_eq!(fs.get(Path::new("/dst/same.log")).unwrap().content, b"line X\n");
+        assert_eq!(fs.get(Path::new("/dst/short.log")).unwrap().content, b"new\n");
+    }
+
+    #[test]
+    fn hash_file_known_digests() {
+        let dir = TempDir::new();
+        let empty = dir.write("empty", "");
+        let abc = dir.write("abc", "abc");
+        let digest = |path: &Path, algo| to_hex(&hash_file(&RealFileSystem, path, algo).unwrap());
+
+        assert_eq!(digest(&empty, HashAlgo::BLAKE3), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(digest(&abc, HashAlgo::BLAKE3), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        assert_eq!(digest(&empty, HashAlgo::SHA256), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(digest(&abc, HashAlgo::SHA256), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(digest(&empty, HashAlgo::XXH3), "99aa06d3014798d86001c324468d497f");
+
+        let yaml = "from:\n  path: /src\nto:\n  path: /dst\nhash_algo: ";
+        assert_eq!(read_yaml(&format!("{}sha256", yaml)).unwrap()[0].hash_algo, HashAlgo::SHA256);
+        assert_eq!(read_yaml(&format!("{}xxh3", yaml)).unwrap()[0].hash_algo, HashAlgo::XXH3);
+        assert!(read_yaml(&format!("{}md5", yaml)).is_err());
+    }
+
+    #[test]
+    fn summary_with_absolute_paths() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/a.txt", "new", 100);
+        fs.add_file("/dst/old.txt", "old", 100);
+        let result = decide(fs, "");
+        let options = SummaryOptions { max_lines: None, absolute_paths: true, deletions_disabled: false };
+        let summary = result.summary_with_options(&options);
+        let (src, dst) = (Path::new("/src").join("a.txt"), Path::new("/dst").join("a.txt"));
+        assert!(summary.contains(&format!("\t{} => {}\n", src.display(), dst.display())), "{}", summary);
+        assert!(summary.contains(&format!("\t{}\n", Path::new("/dst").join("old.txt").display())), "{}", summary);
+        // 默认只显示相对路径
+        let summary = result.summary();
+        assert!(!summary.contains("/src"), "{}", summary);
+
+        #[cfg(target_os = "windows")]
+        assert_eq!(adjust_canonicalization(r"\\?\C:\data\a.txt".to_string()), r"C:\data\a.txt");
+    }
+
+    #[test]
+    fn config_type_mismatch_errors() {
+        let error = |config: &str| {
+            let yaml = format!("from:\n  path: /src\nto:\n  path: /dst\n{}", config);
+            read_yaml(&yaml).err().map(|e| e.to_string()).unwrap_or_default()
+        };
+        // 布尔值写成字符串、数字时只警告，按默认值处理
+        let yaml = "from:\n  path: /src\nto:\n  path: /dst\nrecursive: \"yes\"";
+        let context = read_yaml(yaml).unwrap().remove(0);
+        assert!(!context.recursive);
+        // 按默认值处理会误删文件的选项直接报错
+        assert_eq!(error("delete: 0"), "delete 应为布尔值，但得到整数");
+        assert_eq!(error("protect_destination_created_during_run: \"yes\""),
+                   "protect_destination_created_during_run 应为布尔值，但得到字符串");
+
+        let yaml = "from:\n  path: [/a, /b]\nto:\n  path: /dst";
+        assert_eq!(read_yaml(yaml).err().unwrap().to_string(), "from.path 应为字符串，但得到数组");
+        let yaml = "from: /src\nto:\n  path: /dst";
+        assert_eq!(read_yaml(yaml).err().unwrap().to_string(), "from 应为表，但得到字符串");
+        let yaml = "from:\n  path: /src\n  recursive: \"yes\"\nto:\n  path: /dst";
+        assert_eq!(read_yaml(yaml).err().unwrap().to_string(), "from.recursive 应为布尔值，但得到字符串");
+        let yaml = "from:\n  path: /src\n  exclude: tmp$\nto:\n  path: /dst";
+        assert_eq!(read_yaml(yaml).err().unwrap().to_string(), "from.exclude 应为数组，但得到字符串");
+        assert_eq!(error("threads: four"), "threads 应为整数，但得到字符串");
+        assert_eq!(error("retries: -1"), "retries 应为非负整数，但得到 -1");
+        assert_eq!(error("max_path_length: 1.5"), "max_path_length 应为整数，但得到浮点数");
+        assert_eq!(error("trash_retention_days: [7]"), "trash_retention_days 应为整数，但得到数组");
+        assert_eq!(error("delete_rate_limit: fast"), "delete_rate_limit 应为数字，但得到字符串");
+        assert_eq!(error("log_file: [a, b]"), "log_file 应为字符串，但得到数组");
+    }
+
+    #[test]
+    fn sync_into_zip() {
+        let dir = TempDir::new();
+        dir.write("src/a.txt", "aaa");
+        dir.write("src/docs/b.txt", "bbb");
+        let yaml = format!("from:\n  path: '{}'\nto:\n  path: '{}'\nrecursive: true",
+                           dir.path("src").display(), dir.path("out.zip").display());
+        let context = read_yaml(&yaml).unwrap().remove(0);
+        let zip_path = dir.path("out.zip");
+        let decision = ZipDestination::make_decision(&context, 0).unwrap();
+        assert_eq!(decision.add_items.values().flatten().count(), 2);
+        ZipDestination::execute(&decision, &context).unwrap();
+        let before = ZipDestination::read_entries(&zip_path).unwrap();
+        let mut names: Vec<_> = before.keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "docs/b.txt"]);
+        assert_eq!(before["a.txt"], (3, crc32_file(&dir.path("src/a.txt")).unwrap()));
+
+        // 再次同步时没有变化
+        assert_eq!(ZipDestination::make_decision(&context, 0).unwrap().total_count(), 0);
+
+        // 只更新内容变化的条目
+        dir.write("src/docs/b.txt", "bbbb");
+        let decision = ZipDestination::make_decision(&context, 0).unwrap();
+        assert_eq!(decision.total_count(), 1);
+        assert_eq!(entries(&decision.update_items),
+                   vec![(Path::new("docs").join("b.txt").display().to_string(), Some(UpdateReason::SizeDiffers))]);
+        ZipDestination::execute(&decision, &context).unwrap();
+        let after = ZipDestination::read_entries(&zip_path).unwrap();
+        assert_eq!(after["a.txt"], before["a.txt"]);
+        assert_eq!(after["docs/b.txt"], (4, crc32_file(&dir.path("src/docs/b.txt")).unwrap()));
+        assert_eq!(after.len(), 2);
+        let mut archive = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mut content = String::new();
+        archive.by_name("docs/b.txt").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "bbbb");
+    }
+
+    #[test]
+    fn skip_paths_over_max_length() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/short.txt", "short", 100);
+        fs.add_file("/src/a_very_long_file_name.txt", "long", 100);
+        // 目录本身不长，但其中有过长的路径
+        fs.add_file("/src/d/another_long_name.txt", "long", 100);
+        let context = memory_context(fs.clone(), "max_path_length: 20");
+        let decision = decide_with(&context);
+        assert_eq!(paths(&decision.add_items), vec!["short.txt"]);
+        assert_eq!(paths(&decision.skipped_items), vec!["a_very_long_file_name.txt", "d"]);
+        let summary = decision.summary();
+        assert!(summary.contains("· 跳过(路径过长)：\n\ta_very_long_file_name.txt\n\td\n"), "{}", summary);
+
+        DecisionExecuteTask::new(decision, context).execute().unwrap();
+        assert!(fs.get(Path::new("/dst/short.txt")).is_ok());
+        assert!(fs.get(Path::new("/dst/a_very_long_file_name.txt")).is_err());
+        assert!(fs.get(Path::new("/dst/d")).is_err());
+
+        // on_long_path: error 时整组不执行
+        let dir = TempDir::new();
+        dir.write("src/short.txt", "short");
+        dir.write("src/a_very_long_file_name.txt", "long");
+        let max_path_length = path_length(dir.path("dst/short.txt").to_str().unwrap());
+        let config = format!("max_path_length: {}\non_long_path: error", max_path_length);
+        fs::create_dir_all(dir.path("dst")).unwrap();
+        let error = run_in(&dir, &config, &["--yes"]).unwrap_err();
+        assert!(error.to_string().contains("超过 max_path_length"), "{}", error);
+        assert!(!dir.path("dst/short.txt").exists());
+    }
+
+    #[test]
+    fn prime_aligns_times_without_copying() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/a.txt", "same", 100);
+        fs.add_file("/dst/a.txt", "same", 200);
+        fs.add_file("/src/sub/b.txt", "same", 100);
+        fs.add_file("/dst/sub/b.txt", "same", 300);
+        // 不加 --prime 时按修改时间全部更新
+        assert_eq!(decide(fs.clone(), "").update_items.values().flatten().count(), 2);
+
+        let mut context = read_yaml("from:\n  path: /src\nto:\n  path: /dst\nrecursive: true").unwrap().remove(0)
+            .with_file_system(fs.clone());
+        context.prime = true;
+        let context = Arc::new(context);
+        let decision = decide_with(&context);
+        assert!(decision.add_items.is_empty() && decision.update_items.is_empty());
+        let mut touched = paths(&decision.touch_items);
+        touched.sort();
+        assert_eq!(touched, vec!["a.txt".to_string(), Path::new("sub").join("b.txt").display().to_string()]);
+        DecisionExecuteTask::new(decision, context.clone()).execute().unwrap();
+
+        assert!(fs.copied.lock().unwrap().is_empty());
+        for path in ["/dst/a.txt", "/dst/sub/b.txt"] {
+            assert_eq!(fs.get(Path::new(path)).unwrap().modified, UNIX_EPOCH + Duration::from_secs(100));
+        }
+        assert!(decide(fs, "").is_empty());
+    }
+
+    #[test]
+    fn storage_impact_of_plan() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/new.txt", &"n".repeat(100), 100);
+        fs.add_file("/src/new_dir/inner.txt", &"i".repeat(50), 100);
+        fs.add_file("/dst/old.txt", &"o".repeat(300), 100);
+        fs.add_file("/src/changed.txt", &"c".repeat(10), 100);
+        fs.add_file("/dst/changed.txt", &"c".repeat(40), 100);
+        let decision = decide(fs, "");
+        assert_eq!(decision.storage_impact(), (150, 300, -30));
+        let summary = decision.summary();
+        assert!(summary.contains("· 空间变化：新增 150 B，释放 300 B，更新 -30 B，合计 -180 B\n"), "{}", summary);
+    }
+
+    #[test]
+    fn pipeline_matches_phased() {
+        let sync = |args: &[&str]| {
+            let dir = TempDir::new();
+            dir.write("src/new.txt", "new");
+            dir.write("src/a/b/c/deep.txt", "deep");
+            dir.write("src/a/changed.txt", "changed!");
+            dir.write("dst/a/changed.txt", "old");
+            dir.write("dst/a/stale.txt", "stale");
+            dir.write("dst/gone/inner.txt", "gone");
+            dir.write("src/same.txt", "same");
+            dir.write("dst/same.txt", "same");
+            run_in(&dir, "", args).unwrap();
+            (tree(&dir.path("src")), tree(&dir.path("dst")))
+        };
+        let (src, phased) = sync(&["-y"]);
+        assert_eq!(phased, src);
+        assert_eq!(sync(&["-y", "--pipeline"]).1, phased);
+    }
+
+    #[test]
+    fn protect_destination_created_during_run() {
+        let dir = TempDir::new();
+        dir.write("src/keep.txt", "keep");
+        dir.write("dst/gone.txt", "gone");
+        dir.write("dst/replaced.txt", "old");
+        dir.write("dst/stale/old.txt", "old");
+        // 文件系统的时间戳精度较粗，与运行开始时间拉开距离
+        thread::sleep(Duration::from_millis(50));
+        let context = disk_context(&dir, "protect_destination_created_during_run: true");
+        let decision = decide_with(&context);
+        assert_eq!(paths(&decision.del_items), vec!["gone.txt", "replaced.txt", "stale"]);
+
+        // 分析之后其他进程新建的内容
+        thread::sleep(Duration::from_millis(50));
+        fs::remove_file(dir.path("dst/replaced.txt")).unwrap();
+        dir.write("dst/replaced.txt", "new");
+        dir.write("dst/stale/fresh.txt", "new");
+        DecisionExecuteTask::new(decision, context).execute().unwrap();
+
+        assert!(!dir.path("dst/gone.txt").exists());
+        assert_eq!(dir.read("dst/replaced.txt"), b"new");
+        assert!(dir.path("dst/stale/fresh.txt").exists());
+    }
+
+    #[test]
+    fn excluded_dirs_not_read() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/target/debug/app.o", "obj", 100);
+        fs.add_file("/src/web/node_modules/lib/index.js", "js", 100);
+        fs.add_file("/src/web/app.js", "app", 100);
+        fs.add_file("/src/main.rs", "main", 100);
+        fs.add_dir("/dst");
+        let yaml = "from:\n  path: /src\n  exclude: ['^target']\n  exclude_glob: ['**/node_modules']\n\
+                    to:\n  path: /dst\nrecursive: true";
+        let context = Arc::new(read_yaml(yaml).unwrap().remove(0).with_file_system(fs.clone()));
+        let decision = decide_with(&context);
+        assert_eq!(paths(&decision.add_items), vec!["main.rs", "web"]);
+
+        let read_dirs = fs.read_dirs.lock().unwrap();
+        assert!(read_dirs.contains(&PathBuf::from("/src/web")), "{:?}", read_dirs);
+        assert!(read_dirs.iter().all(|dir| !dir.starts_with("/src/target") && !dir.starts_with("/src/web/node_modules")),
+                "{:?}", read_dirs);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn broken_symlink_policies() {
+        let dir = TempDir::new();
+        dir.write("src/ok.txt", "ok");
+        std::os::unix::fs::symlink("missing.txt", dir.path("src/broken")).unwrap();
+        fs::create_dir_all(dir.path("dst")).unwrap();
+
+        let context = disk_context(&dir, "symlinks: follow");
+        let error = get_dict_info(&context, 0).unwrap_err();
+        assert!(format!("{:#}", error).contains("符号链接指向的目标不存在"), "{:#}", error);
+
+        let decision = decide_with(&disk_context(&dir, "symlinks: skip"));
+        assert_eq!(paths(&decision.add_items), vec!["ok.txt"]);
+
+        let context = disk_context(&dir, "symlinks: preserve");
+        let decision = decide_with(&context);
+        assert_eq!(paths(&decision.add_items), vec!["broken", "ok.txt"]);
+        DecisionExecuteTask::new(decision, context.clone()).execute().unwrap();
+        assert_eq!(fs::read_link(dir.path("dst/broken")).unwrap(), Path::new("missing.txt"));
+        assert!(decide_with(&context).is_empty());
+    }
+
+    #[test]
+    fn save_and_apply_plan() {
+        let dir = TempDir::new();
+        dir.write("src/new.txt", "new");
+        dir.write("src/sub/changed.txt", "changed!");
+        dir.write("dst/sub/changed.txt", "old");
+        dir.write("dst/stale.txt", "stale");
+        let plan = dir.path("plan.json");
+        let plan = plan.to_str().unwrap();
+        run_in(&dir, "", &["-y", "--save-plan", plan]).unwrap();
+        // 保存计划时不执行
+        assert!(!dir.path("dst/new.txt").exists());
+
+        run_in(&dir, "", &["-y", "--apply-plan", plan]).unwrap();
+        assert_eq!(tree(&dir.path("dst")), tree(&dir.path("src")));
+
+        // 保存计划后目标文件被修改（大小不变），执行时中止
+        dir.write("dst/sub/changed.txt", "changed?");
+        let changed = dir.write("src/sub/changed.txt", "changed.");
+        filetime::set_file_mtime(&changed, FileTime::from_unix_time(1_000_000_000, 0)).unwrap();
+        run_in(&dir, "", &["-y", "--save-plan", plan]).unwrap();
+        dir.write("dst/sub/changed.txt", "edited!!");
+        let error = run_in(&dir, "", &["-y", "--apply-plan", plan]).unwrap_err();
+        assert!(error.to_string().contains("在保存计划后发生了变化"), "{}", error);
+        assert!(error.to_string().contains("目标文件内容变化"), "{}", error);
+        assert_eq!(dir.read("dst/sub/changed.txt"), b"edited!!");
+    }
+
+    #[test]
+    fn scan_only_journaled_dirs() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        fs.add_file("/src/a/new.txt", "new", 100);
+        fs.add_file("/src/b/new.txt", "new", 100);
+        fs.add_file("/dst/b/old.txt", "old", 100);
+        fs.add_dir("/dst/a");
+        let full = decide(fs.clone(), "");
+        assert_eq!(paths(&full.add_items), vec![Path::new("a").join("new.txt").display().to_string(),
+                                                Path::new("b").join("new.txt").display().to_string()]);
+        fs.read_dirs.lock().unwrap().clear();
+
+        // 模拟 USN 日志中只有 a 有变动
+        let mut context = read_yaml("from:\n  path: /src\nto:\n  path: /dst\nrecursive: true").unwrap().remove(0)
+            .with_file_system(fs.clone());
+        context.changed_dirs = Some(Arc::new(HashSet::from([PathBuf::from("a")])));
+        let decision = decide_with(&Arc::new(context));
+        assert_eq!(paths(&decision.add_items), vec![Path::new("a").join("new.txt").display().to_string()]);
+        assert!(decision.del_items.is_empty());
+        let read_dirs = fs.read_dirs.lock().unwrap();
+        assert!(!read_dirs.iter().any(|dir| dir.ends_with("b")), "{:?}", read_dirs);
+    }
+}